@@ -0,0 +1,36 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use frosk::core::dsp::{CorrelationMethod, SignalProcessor};
+use rand::prelude::*;
+use std::time::Instant;
+
+const SAMPLE_RATE: usize = 44100;
+const CHUNK_SIZE: usize = 440;
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    // how long does it take to correlate one second of audio, 440 samples at a time
+    let source = (0..SAMPLE_RATE)
+        .map(|_| random::<f32>() - 0.5)
+        .collect::<Vec<f32>>();
+
+    let mut group = c.benchmark_group("correlation");
+    group.sample_size(10);
+
+    for (name, method) in [
+        ("Naive", CorrelationMethod::Naive),
+        ("Fft", CorrelationMethod::Fft),
+    ] {
+        let mut signal_processor = SignalProcessor::new(method);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                source.chunks(CHUNK_SIZE).for_each(|chunk| {
+                    signal_processor.correlate(chunk, Instant::now());
+                });
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,523 @@
+use anyhow::Result;
+use clap::Parser;
+use serde::Serialize;
+
+use std::{
+    path::PathBuf,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use frosk::core::{
+    capture::{
+        audio_capture,
+        device::{input_devices, DeviceSelector},
+        file::{FileCapturer, ReplaySpeed},
+        pulse::{sink_inputs, PulseSource},
+        AudioCapture, CaptureBackend, CaptureHandle,
+    },
+    config::{Config, ConfigWatcher, TemplateConfig},
+    dsp::{AdaptiveThreshold, DetectorConfig, SignalProcessor, ThresholdMode},
+    event::FroskEvent,
+    fishing::{Fisher, FishingConfig, FishingState, KeyBindings},
+    focus::{FocusPolicy, GameFocus},
+    input::{EnigoBackend, KeyBinding},
+    recorder::{RecordMode, Recorder, RecorderHandle},
+};
+
+/// Detect fish bites without the GUI, printing events and correlation stats to stdout.
+///
+/// Settings are read from the config file and hot-reloaded when it changes. The
+/// options below override the corresponding settings in the file.
+#[derive(Parser, Clone)]
+struct Args {
+    /// Config file to use instead of the one in the platform's config directory
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// List the available input devices with their supported configs and exit
+    #[arg(long)]
+    list_devices: bool,
+    /// Name of the input device to capture from, instead of the game's own output
+    #[arg(long, group = "device_selector")]
+    device: Option<String>,
+    /// Position of the input device to capture from in --list-devices
+    #[arg(long, group = "device_selector")]
+    device_index: Option<usize>,
+    /// Capture from the first input device whose name matches this regex
+    #[arg(long, group = "device_selector")]
+    device_regex: Option<String>,
+    /// On Linux, only capture the application whose name or binary contains this, e.g. Wow.exe
+    #[arg(long, group = "pulse")]
+    application: Option<String>,
+    /// On Linux, the PulseAudio or PipeWire source to capture from, e.g. frosk.monitor
+    #[arg(long, group = "pulse")]
+    pulse_source: Option<String>,
+    /// Replay a WAV or FLAC recording instead of capturing the game audio, resampled
+    /// to the rate of the templates
+    #[arg(long)]
+    file: Option<PathBuf>,
+    /// Replay the recording as fast as possible instead of in real time
+    #[arg(long, requires = "file")]
+    fast: bool,
+    /// Sound to detect as KIND=WAV_FILE, e.g. cast-splash=splash.wav. May be repeated,
+    /// and replaces the configured templates when given
+    #[arg(long = "template", value_name = "KIND=WAV_FILE")]
+    templates: Vec<TemplateConfig>,
+    /// Use the FFT correlation engine instead of the naive dot product
+    #[arg(long)]
+    fft: bool,
+    /// Normalize by the energy of the captured window as well, making scores volume independent
+    #[arg(long)]
+    normalized: bool,
+    /// Correlation a template has to exceed to be reported
+    #[arg(long)]
+    threshold: Option<f32>,
+    /// Correlation the score has to fall below before a template can be reported again
+    #[arg(long)]
+    rearm_level: Option<f32>,
+    /// Minimum milliseconds between two reports of the same template
+    #[arg(long)]
+    refractory_ms: Option<u32>,
+    /// Milliseconds a score has to stop rising before its peak is reported
+    #[arg(long)]
+    peak_lookahead_ms: Option<u32>,
+    /// Report scores standing out SIGMAS standard deviations from the background
+    /// of the correlation stream, instead of using --threshold and --rearm-level
+    #[arg(long, value_name = "SIGMAS")]
+    adaptive: Option<f32>,
+    /// Milliseconds to wait for a bite before casting again
+    #[arg(long)]
+    cast_timeout_ms: Option<u32>,
+    /// In-game key for interacting with the target, which reels in the catch, e.g. Shift+F9
+    #[arg(long)]
+    reel_key: Option<KeyBinding>,
+    /// In-game key for casting Fishing
+    #[arg(long)]
+    cast_key: Option<KeyBinding>,
+    /// Only report events, without sending any key presses
+    #[arg(long)]
+    dry_run: bool,
+    /// Print JSON lines instead of plain text
+    #[arg(long)]
+    json: bool,
+    /// Record the captured audio to WAV files in the configured recording folder
+    #[arg(long)]
+    record: bool,
+    /// Only record the audio around each event, implies --record
+    #[arg(long)]
+    record_clips: bool,
+    /// Seconds of audio covered by each stats line, 0 disables stats
+    #[arg(long, default_value_t = 1.0)]
+    stats_interval: f32,
+}
+
+impl Args {
+    /// Overrides the settings of `config` given on the command line.
+    fn apply(&self, config: &mut Config) {
+        if self.device.is_some() || self.device_index.is_some() || self.device_regex.is_some() {
+            config.capture.backend = CaptureBackend::Device;
+        }
+        if let Some(name) = &self.device {
+            config.capture.device = DeviceSelector::Name(name.clone());
+        }
+        if let Some(index) = self.device_index {
+            config.capture.device = DeviceSelector::Index(index);
+        }
+        if let Some(regex) = &self.device_regex {
+            config.capture.device = DeviceSelector::Regex {
+                regex: regex.clone(),
+            };
+        }
+        if self.application.is_some() || self.pulse_source.is_some() {
+            config.capture.backend = CaptureBackend::Game;
+        }
+        if let Some(application) = &self.application {
+            config.capture.pulse = PulseSource::Application(application.clone());
+        }
+        if let Some(source) = &self.pulse_source {
+            config.capture.pulse = PulseSource::Source(source.clone());
+        }
+        config.override_detection(&self.templates, self.fft, self.normalized);
+        let detector = &mut config.detection.detector;
+        detector.threshold = self.threshold.unwrap_or(detector.threshold);
+        detector.rearm_level = self.rearm_level.unwrap_or(detector.rearm_level);
+        detector.refractory_ms = self.refractory_ms.unwrap_or(detector.refractory_ms);
+        detector.peak_lookahead_ms = self.peak_lookahead_ms.unwrap_or(detector.peak_lookahead_ms);
+        if let Some(sigmas) = self.adaptive {
+            detector.threshold_mode = ThresholdMode::Adaptive(AdaptiveThreshold {
+                sigmas,
+                rearm_sigmas: sigmas / 2.0,
+                ..AdaptiveThreshold::default()
+            });
+        }
+        config.fishing.cast_timeout_ms = self
+            .cast_timeout_ms
+            .unwrap_or(config.fishing.cast_timeout_ms);
+        config.keys.reel = self.reel_key.unwrap_or(config.keys.reel);
+        config.keys.cast = self.cast_key.unwrap_or(config.keys.cast);
+        if self.record || self.record_clips {
+            config.recording.enabled = true;
+        }
+        if self.record_clips {
+            config.recording.mode = RecordMode::Clips;
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Report {
+    Event {
+        /// Seconds of audio processed when the event fired
+        time: f64,
+        /// Seconds into the audio at which the matched sound starts
+        match_time: f64,
+        #[serde(flatten)]
+        event: FroskEvent,
+    },
+    Stats(CorrelationStats),
+    /// The fishing cycle moved on to another state
+    State {
+        state: FishingState,
+    },
+    /// A timer of the fishing cycle ran out
+    Timer {
+        #[serde(flatten)]
+        event: FroskEvent,
+    },
+}
+
+/// Correlation stats of each template, in the order they were added.
+#[derive(Debug, Clone, Serialize)]
+struct CorrelationStats {
+    /// Seconds of audio processed at the end of the interval
+    time: f64,
+    templates: Vec<TemplateStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct TemplateStats {
+    template: String,
+    mean: f32,
+    min: f32,
+    max: f32,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    if args.list_devices {
+        for device in input_devices()? {
+            let default = if device.is_default { " (default)" } else { "" };
+            println!("{}: {}{default}", device.index, device.name);
+            for config in &device.configs {
+                println!("    {config}");
+            }
+        }
+        if cfg!(target_os = "linux") {
+            match sink_inputs() {
+                Ok(sink_inputs) => {
+                    println!("applications playing audio:");
+                    for sink_input in sink_inputs {
+                        println!(
+                            "{}: {} ({})",
+                            sink_input.index,
+                            sink_input.application_name().unwrap_or_default(),
+                            sink_input.binary().unwrap_or_default()
+                        );
+                    }
+                }
+                Err(err) => eprintln!("warning: {err:#}"),
+            }
+        }
+        return Ok(());
+    }
+
+    let config_path = args.config.clone().or_else(Config::default_path);
+    let mut config = match &config_path {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    args.apply(&mut config);
+    let signal_processor = config.signal_processor()?;
+
+    // settings that can change on the fly are passed on as the config file is reloaded
+    let (detector_tx, detector_rx) = mpsc::channel::<DetectorConfig>();
+    let (fishing_tx, fishing_rx) = mpsc::channel::<(FishingConfig, KeyBindings)>();
+    if let Some(path) = &config_path {
+        let args = args.clone();
+        ConfigWatcher::new(path).spawn(Duration::from_secs(1), move |mut config| {
+            args.apply(&mut config);
+            let _ = detector_tx.send(config.detection.detector);
+            let _ = fishing_tx.send((config.fishing_config(), config.keys));
+        });
+    }
+
+    let (event_tx, event_rx) = mpsc::channel::<FroskEvent>();
+    let json = args.json;
+    let dry_run = args.dry_run;
+    let fishing_config = config.fishing_config();
+    let key_bindings = config.keys;
+    let game_process = config.capture.process.clone();
+    let (ready_tx, ready_rx) = mpsc::channel::<()>();
+    let event_handler_thread = thread::spawn(move || -> Result<()> {
+        if dry_run {
+            return Ok(());
+        }
+        let mut fisher =
+            Fisher::new(fishing_config, EnigoBackend::new()?).with_key_bindings(key_bindings);
+        match GameFocus::new(game_process) {
+            Ok(focus) => fisher = fisher.with_focus_guard(focus),
+            Err(err) if fishing_config.focus != FocusPolicy::Off => {
+                eprintln!("warning: keys are pressed whichever window has focus: {err:#}")
+            }
+            Err(_) => {}
+        }
+        let _ = ready_tx.send(());
+        let mut state = fisher.state();
+        loop {
+            for (fishing_config, key_bindings) in fishing_rx.try_iter() {
+                fisher.set_config(fishing_config);
+                fisher.set_key_bindings(key_bindings);
+            }
+            match event_rx.recv_timeout(Duration::from_millis(50)) {
+                Ok(event) => fisher.handle_event(&event)?,
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Some(event) = fisher.tick()? {
+                        emit(&Report::Timer { event }, json)?;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            if fisher.state() != state {
+                state = fisher.state();
+                emit(&Report::State { state }, json)?;
+            }
+        }
+    });
+
+    // the input backend is set up before capturing, so e.g. a missing display fails right away
+    if !dry_run && ready_rx.recv().is_err() {
+        event_handler_thread.join().unwrap()?;
+        anyhow::bail!("the event handler stopped unexpectedly");
+    }
+
+    // templates are resampled to the rate of the first, and the audio is converted to it too
+    let sample_rate = signal_processor.sample_rate();
+    let (report_tx, report_rx) = mpsc::channel();
+    let capture = match &args.file {
+        Some(path) => {
+            let speed = if args.fast {
+                ReplaySpeed::AsFastAsPossible
+            } else {
+                ReplaySpeed::RealTime
+            };
+            let capturer = FileCapturer::open(path)?
+                .resampled(sample_rate)
+                .with_chunk_size(config.capture.chunk_size as usize)
+                .with_speed(speed);
+            start_detection(
+                &capturer,
+                signal_processor,
+                sample_rate,
+                args.stats_interval,
+                recorder(&config, sample_rate)?,
+                detector_rx,
+                report_tx,
+            )?
+        }
+        None => {
+            let capturer = audio_capture(config.capture.clone(), sample_rate);
+            start_detection(
+                &capturer,
+                signal_processor,
+                sample_rate,
+                args.stats_interval,
+                recorder(&config, sample_rate)?,
+                detector_rx,
+                report_tx,
+            )?
+        }
+    };
+
+    // the loop ends once the capturer drops its callback, e.g. at the end of a recording
+    loop {
+        match report_rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(report) => {
+                emit(&report, args.json)?;
+                if let Report::Event { event, .. } = report {
+                    // the event handler only hangs up when it failed, so report why
+                    if !args.dry_run && event_tx.send(event).is_err() {
+                        capture.stop();
+                        event_handler_thread.join().unwrap()?;
+                        anyhow::bail!("the event handler stopped unexpectedly");
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        for err in capture.errors() {
+            eprintln!("capture error: {err:#}");
+        }
+    }
+
+    capture.stop();
+    drop(event_tx);
+    event_handler_thread.join().unwrap()
+}
+
+fn emit(report: &Report, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string(report)?);
+    } else {
+        print_report(report);
+    }
+    Ok(())
+}
+
+fn recorder(config: &Config, sample_rate: u32) -> Result<Option<RecorderHandle>> {
+    if !config.recording.enabled {
+        return Ok(None);
+    }
+    eprintln!("recording to {}", config.recording.dir.display());
+    let recorder = Recorder::new(config.recording.clone(), sample_rate)?;
+    Ok(Some(recorder.spawn()?))
+}
+
+fn start_detection(
+    capturer: &impl AudioCapture,
+    mut signal_processor: SignalProcessor,
+    sample_rate: u32,
+    stats_interval: f32,
+    mut recorder: Option<RecorderHandle>,
+    detector_configs: mpsc::Receiver<DetectorConfig>,
+    reports: mpsc::Sender<Report>,
+) -> Result<CaptureHandle> {
+    let hop = signal_processor.hop() as u64;
+    let stats_samples = (stats_interval * sample_rate as f32) as u64;
+    let mut samples_processed: u64 = 0;
+    let mut stats = StatsAccumulator::new(
+        signal_processor
+            .templates()
+            .map(|template| template.name.clone())
+            .collect(),
+    );
+
+    capturer.capture_game_audio(move |chunk, captured_at| {
+        for detector_config in detector_configs.try_iter() {
+            signal_processor.set_detector_config(detector_config);
+        }
+        // the chunk is recorded first, so a clip holds the audio an event was detected in
+        if let Some(recorder) = &recorder {
+            recorder.push(chunk);
+        }
+        for scores in signal_processor.correlate(chunk, captured_at) {
+            samples_processed += hop;
+            let time = samples_processed as f64 / sample_rate as f64;
+
+            // a closed channel means main is shutting down, so send errors are ignored
+            for (template, &correlation) in scores.iter().enumerate() {
+                if let Some(event) = signal_processor.determine_event(template, correlation) {
+                    if let Some(recorder) = &recorder {
+                        recorder.mark(&event);
+                    }
+                    let match_time = event.detection().map_or(0.0, |detection| {
+                        detection.sample_offset as f64 / sample_rate as f64
+                    });
+                    let _ = reports.send(Report::Event {
+                        time,
+                        match_time,
+                        event,
+                    });
+                }
+            }
+
+            if stats_samples > 0 {
+                stats.push(&scores, hop);
+                if stats.samples >= stats_samples {
+                    let _ = reports.send(Report::Stats(stats.finish(time)));
+                }
+            }
+        }
+
+        // the recorder stops after its first error, e.g. a full disk
+        if let Some(err) = recorder.as_ref().and_then(RecorderHandle::error) {
+            eprintln!("recording stopped: {err:#}");
+            recorder = None;
+        }
+    })
+}
+
+struct StatsAccumulator {
+    names: Vec<String>,
+    samples: u64,
+    count: u32,
+    sums: Vec<f32>,
+    mins: Vec<f32>,
+    maxs: Vec<f32>,
+}
+
+impl StatsAccumulator {
+    fn new(names: Vec<String>) -> Self {
+        let n = names.len();
+        Self {
+            names,
+            samples: 0,
+            count: 0,
+            sums: vec![0.0; n],
+            mins: vec![f32::INFINITY; n],
+            maxs: vec![f32::NEG_INFINITY; n],
+        }
+    }
+
+    fn push(&mut self, scores: &[f32], samples: u64) {
+        self.samples += samples;
+        self.count += 1;
+        for (i, &score) in scores.iter().enumerate() {
+            self.sums[i] += score;
+            self.mins[i] = self.mins[i].min(score);
+            self.maxs[i] = self.maxs[i].max(score);
+        }
+    }
+
+    fn finish(&mut self, time: f64) -> CorrelationStats {
+        let templates = self
+            .names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| TemplateStats {
+                template: name.clone(),
+                mean: self.sums[i] / self.count as f32,
+                min: self.mins[i],
+                max: self.maxs[i],
+            })
+            .collect();
+        *self = Self::new(std::mem::take(&mut self.names));
+        CorrelationStats { time, templates }
+    }
+}
+
+fn print_report(report: &Report) {
+    match report {
+        Report::Event {
+            time,
+            match_time,
+            event,
+        } => println!(
+            "{time:>9.3}s  {:<13} score={:.3} start={match_time:.3}s",
+            event.name(),
+            event.detection().map_or(0.0, |detection| detection.score)
+        ),
+        Report::Stats(stats) => {
+            for template in &stats.templates {
+                println!(
+                    "{:>9.3}s  stats {:<13} mean={:.3} min={:.3} max={:.3}",
+                    stats.time, template.template, template.mean, template.min, template.max
+                );
+            }
+        }
+        Report::State { state } => println!("{:>9}   state {state:?}", ""),
+        Report::Timer { event } => println!("{:>9}   timer {}", "", event.name()),
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::{anyhow, bail, Result};
+use clap::Parser;
+
+use std::{fs, path::PathBuf};
+
+use frosk::core::{
+    config::{Config, TemplateConfig},
+    dsp::ThresholdMode,
+    eval::{curve_csv, load_dir, sweep, with_threshold, Correlated, Metrics},
+};
+
+/// Measure how well bites are detected in a directory of labelled recordings.
+///
+/// Each WAV or FLAC recording needs a label file with the same name and a `.txt`
+/// extension, listing the second at which each bite sound starts on its own line.
+/// Audacity label tracks exported as text can be used as they are.
+#[derive(Parser)]
+struct Args {
+    /// Directory holding the recordings and their label files
+    dir: PathBuf,
+    /// Config file to take the detection settings from instead of the one in the
+    /// platform's config directory
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Sound to detect as KIND=WAV_FILE, replacing the configured templates when given
+    #[arg(long = "template", value_name = "KIND=WAV_FILE")]
+    templates: Vec<TemplateConfig>,
+    /// Use the FFT correlation engine instead of the naive dot product
+    #[arg(long)]
+    fft: bool,
+    /// Normalize by the energy of the captured window as well, making scores volume independent
+    #[arg(long)]
+    normalized: bool,
+    /// Threshold to report the results of each recording at, the configured one by default.
+    /// Standard deviations when the config uses an adaptive threshold
+    #[arg(long)]
+    threshold: Option<f32>,
+    /// Thresholds to sweep as FROM:TO:STEP
+    #[arg(long, default_value = "0.05:1:0.05")]
+    sweep: String,
+    /// Milliseconds a detected sound may start away from a labelled bite and still count
+    #[arg(long, default_value_t = 500)]
+    tolerance_ms: u32,
+    /// Write the metrics of every threshold in the sweep to this CSV file
+    #[arg(long)]
+    csv: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let thresholds = parse_sweep(&args.sweep)?;
+    let mut config = match args.config.clone().or_else(Config::default_path) {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+    config.override_detection(&args.templates, args.fft, args.normalized);
+    let detector = config.detection.detector;
+    // the templates are loaded and the FFTs planned once, then the processor
+    // is reset for every recording and threshold
+    let mut processor = config.signal_processor()?;
+    let threshold = args.threshold.unwrap_or(match detector.threshold_mode {
+        ThresholdMode::Fixed => detector.threshold,
+        ThresholdMode::Adaptive(adaptive) => adaptive.sigmas,
+    });
+    let tolerance = args.tolerance_ms as f64 / 1000.0;
+    let chunk_size = config.capture.chunk_size as usize;
+
+    let mut correlated = Vec::new();
+    let mut total = Metrics::default();
+    println!(
+        "{:<32} {:>8} {:>5} {:>5} {:>5} {:>5}",
+        "recording", "seconds", "bites", "hits", "false", "miss"
+    );
+    for recording in load_dir(&args.dir)? {
+        let recording_correlations = Correlated::new(&recording, &mut processor, chunk_size);
+        processor.set_detector_config(with_threshold(detector, threshold));
+        let detections = recording_correlations.detect(&mut processor);
+        processor.set_detector_config(detector);
+        let metrics = recording_correlations.score(&detections, tolerance);
+        let name = recording.path.file_name().unwrap_or_default();
+        println!(
+            "{:<32} {:>8.1} {:>5} {:>5} {:>5} {:>5}",
+            name.to_string_lossy(),
+            metrics.duration,
+            recording.bites.len(),
+            metrics.true_positives,
+            metrics.false_positives,
+            metrics.false_negatives
+        );
+        total.add(&metrics);
+        correlated.push(recording_correlations);
+    }
+
+    println!();
+    println!("at threshold {threshold}:");
+    print_metrics(&total);
+
+    let curve = sweep(&correlated, &thresholds, tolerance, &mut processor);
+    if let Some((best, metrics)) = curve
+        .iter()
+        .max_by(|(_, a), (_, b)| a.f1().total_cmp(&b.f1()))
+    {
+        println!();
+        println!("best F1 of the sweep at threshold {best}:");
+        print_metrics(metrics);
+    }
+    if let Some(path) = &args.csv {
+        fs::write(path, curve_csv(&curve))?;
+    }
+    Ok(())
+}
+
+fn print_metrics(metrics: &Metrics) {
+    println!(
+        "  precision {:.3}  recall {:.3}  F1 {:.3}  false alarms/hour {:.1}",
+        metrics.precision(),
+        metrics.recall(),
+        metrics.f1(),
+        metrics.false_alarms_per_hour()
+    );
+    let latency = |percentile| {
+        metrics
+            .latency_percentile(percentile)
+            .map_or("-".to_string(), |latency| {
+                format!("{:.0}ms", latency * 1000.0)
+            })
+    };
+    println!(
+        "  latency min {}  median {}  p90 {}  max {}",
+        latency(0.0),
+        latency(50.0),
+        latency(90.0),
+        latency(100.0)
+    );
+}
+
+fn parse_sweep(s: &str) -> Result<Vec<f32>> {
+    let bounds: Vec<f64> = s
+        .split(':')
+        .map(|bound| bound.parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| anyhow!("expected FROM:TO:STEP, got {s}"))?;
+    let [from, to, step] = bounds[..] else {
+        bail!("expected FROM:TO:STEP, got {s}");
+    };
+    if step <= 0.0 || to < from {
+        bail!("expected FROM <= TO and a positive STEP, got {s}");
+    }
+    // counting steps rather than adding them up keeps rounding errors from skipping TO
+    let steps = ((to - from) / step + 1e-6).floor() as usize;
+    Ok((0..=steps)
+        .map(|i| (from + i as f64 * step) as f32)
+        .collect())
+}
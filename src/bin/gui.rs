@@ -2,20 +2,29 @@ use anyhow::Result;
 
 use std::{
     collections::VecDeque,
-    sync::{Arc, Mutex},
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex},
     thread,
+    time::Duration,
 };
 
 use eframe::egui::{self, Color32};
 use egui_plot::{Legend, Line, Plot, PlotPoints};
 use frosk::core::{
-    capture::{default_audio_capture, AudioCapture},
-    dsp::SignalProcessor,
-    event::{handle_event, FroskEvent},
+    capture::{
+        audio_capture,
+        device::{input_devices, DeviceSelector},
+        AudioCapture, CaptureHandle,
+    },
+    config::{save_device, save_keys, Config, ConfigWatcher},
+    dsp::DetectorConfig,
+    event::FroskEvent,
+    fishing::{Fisher, FishingState, KeyBindings},
+    focus::{FocusPolicy, GameFocus},
+    input::{EnigoBackend, KeyBinding},
+    recorder::{Recorder, RecorderHandle},
 };
 
-const RETENTION: usize = 8000;
-
 fn main() -> Result<()> {
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -32,10 +41,24 @@ fn main() -> Result<()> {
     //     rb_prod.try_push(0.0).unwrap();
     // }
 
-    let mut signal_processor = SignalProcessor::default();
+    let config_path = Config::default_path();
+    let config = match &config_path {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let retention = config.gui.retention;
+
+    let mut signal_processor = config.signal_processor()?;
+    let template_names: Vec<String> = signal_processor
+        .templates()
+        .map(|template| template.name.clone())
+        .collect();
 
-    let correlations: Arc<Mutex<VecDeque<f32>>> =
-        Arc::new(Mutex::new(VecDeque::from(vec![0.0; RETENTION])));
+    let correlations: Arc<Mutex<Vec<VecDeque<f32>>>> =
+        Arc::new(Mutex::new(vec![
+            VecDeque::from(vec![0.0; retention]);
+            template_names.len()
+        ]));
     let correlations_clone = Arc::clone(&correlations);
     let events: Arc<Mutex<Vec<FroskEvent>>> = Arc::new(Mutex::new(Vec::new()));
     let events_to_be_handled: Arc<Mutex<VecDeque<FroskEvent>>> =
@@ -44,68 +67,296 @@ fn main() -> Result<()> {
     let events_clone = Arc::clone(&events);
     let events_to_be_handled_clone: Arc<Mutex<VecDeque<FroskEvent>>> =
         Arc::clone(&events_to_be_handled);
-    let audio_capture = default_audio_capture();
-    unsafe {
-        audio_capture.capture_game_audio(move |chunk| {
-            for small_chunk in chunk.chunks(10) {
-                signal_processor.process_chunk(small_chunk);
-                let correlation = signal_processor.compute_correlation();
-
-                if let Some(event) = signal_processor.determine_event(correlation) {
+    let fishing_config = Arc::new(Mutex::new(config.fishing_config()));
+    let key_bindings = Arc::new(Mutex::new(config.keys));
+    let (detector_tx, detector_rx) = mpsc::channel::<DetectorConfig>();
+    if let Some(path) = &config_path {
+        let fishing_config = Arc::clone(&fishing_config);
+        let key_bindings = Arc::clone(&key_bindings);
+        // keys edited in the window are only replaced when they are edited in the file as well
+        let mut file_keys = config.keys;
+        ConfigWatcher::new(path).spawn(Duration::from_secs(1), move |config| {
+            let _ = detector_tx.send(config.detection.detector);
+            *fishing_config.lock().unwrap() = config.fishing_config();
+            if config.keys != file_keys {
+                file_keys = config.keys;
+                *key_bindings.lock().unwrap() = config.keys;
+            }
+        });
+    }
+
+    let device_names: Vec<String> = input_devices()
+        .map(|devices| devices.into_iter().map(|device| device.name).collect())
+        .unwrap_or_default();
+    let mut device_picker = DevicePicker {
+        selected: config.capture.device.find(&device_names).ok(),
+        names: device_names,
+        config_path: config_path.clone(),
+        status: None,
+    };
+
+    let mut recorder = match config.recording.enabled {
+        true => Recorder::new(config.recording.clone(), signal_processor.sample_rate())
+            .and_then(Recorder::spawn)
+            .map_err(|err| eprintln!("not recording: {err:#}"))
+            .ok(),
+        false => None,
+    };
+
+    // a missing device is shown in the window, so another one can be picked
+    let audio_capture = audio_capture(config.capture.clone(), signal_processor.sample_rate());
+    let capture_result = audio_capture.capture_game_audio(move |chunk, captured_at| {
+        for detector_config in detector_rx.try_iter() {
+            signal_processor.set_detector_config(detector_config);
+        }
+        if let Some(recorder) = &recorder {
+            recorder.push(chunk);
+        }
+        for scores in signal_processor.correlate(chunk, captured_at) {
+            for (template, &correlation) in scores.iter().enumerate() {
+                if let Some(event) = signal_processor.determine_event(template, correlation) {
+                    if let Some(recorder) = &recorder {
+                        recorder.mark(&event);
+                    }
                     events_clone.lock().unwrap().push(event);
                     events_to_be_handled_clone.lock().unwrap().push_back(event);
                 }
+            }
 
-                {
-                    // put in a block here so the lock will be released immediately
-                    let mut correlations = correlations_clone.lock().unwrap();
-                    correlations.pop_front();
-                    correlations.push_back(correlation);
+            {
+                // put in a block here so the lock will be released immediately
+                let mut correlations = correlations_clone.lock().unwrap();
+                for (history, correlation) in correlations.iter_mut().zip(scores) {
+                    history.pop_front();
+                    history.push_back(correlation);
                 }
             }
-        })?;
-    }
+        }
 
+        if let Some(err) = recorder.as_ref().and_then(RecorderHandle::error) {
+            eprintln!("recording stopped: {err:#}");
+            recorder = None;
+        }
+    });
+    let capture = match capture_result {
+        Ok(capture) => Some(capture),
+        Err(err) => {
+            device_picker.status = Some(format!("{err:#}"));
+            None
+        }
+    };
+
+    let fishing_state = Arc::new(Mutex::new(FishingState::Idle));
+    let fishing_state_clone = Arc::clone(&fishing_state);
+    let key_bindings_clone = Arc::clone(&key_bindings);
+    let fishing_config_clone = Arc::clone(&fishing_config);
     let events_to_be_handled_clone = Arc::clone(&events_to_be_handled);
-    let event_handler_thread = thread::spawn(move || loop {
-        let event = events_to_be_handled_clone.lock().unwrap().pop_front();
-        if let Some(event) = event {
-            handle_event(event).unwrap();
+    let events_clone = Arc::clone(&events);
+    let game_process = config.capture.process.clone();
+    // shown in the window, which keeps plotting after auto-fishing stopped
+    let fishing_error: Arc<Mutex<Option<String>>> = Arc::default();
+    let fishing_error_clone = Arc::clone(&fishing_error);
+    thread::spawn(move || {
+        let report = |err: anyhow::Error| {
+            eprintln!("fishing stopped: {err:#}");
+            *fishing_error_clone.lock().unwrap() = Some(format!("{err:#}"));
+        };
+        let fishing_config = *fishing_config_clone.lock().unwrap();
+        let input = match EnigoBackend::new() {
+            Ok(input) => input,
+            Err(err) => return report(err),
+        };
+        let mut fisher = Fisher::new(fishing_config, input);
+        match GameFocus::new(game_process) {
+            Ok(focus) => fisher = fisher.with_focus_guard(focus),
+            Err(err) if fishing_config.focus != FocusPolicy::Off => {
+                eprintln!("warning: keys are pressed whichever window has focus: {err:#}")
+            }
+            Err(_) => {}
+        }
+        loop {
+            fisher.set_config(*fishing_config_clone.lock().unwrap());
+            fisher.set_key_bindings(*key_bindings_clone.lock().unwrap());
+            let event = events_to_be_handled_clone.lock().unwrap().pop_front();
+            let handled = match event {
+                Some(event) => fisher.handle_event(&event),
+                None => fisher.tick().map(|timeout| {
+                    if let Some(timeout) = timeout {
+                        events_clone.lock().unwrap().push(timeout);
+                    }
+                }),
+            };
+            if let Err(err) = handled {
+                return report(err);
+            }
+            *fishing_state_clone.lock().unwrap() = fisher.state();
+            thread::sleep(Duration::from_millis(50));
         }
-        thread::sleep(std::time::Duration::from_millis(50));
     });
 
     eframe::run_native(
         "frosk",
         options,
         Box::new(|_cc| {
-            Ok(Box::new(MyApp::new(
-                Arc::clone(&events),
-                Arc::clone(&correlations),
-            )))
+            Ok(Box::new(
+                MyApp::new(
+                    Arc::clone(&events),
+                    Arc::clone(&correlations),
+                    Arc::clone(&fishing_state),
+                    Arc::clone(&key_bindings),
+                    template_names,
+                    retention,
+                    device_picker,
+                )
+                .with_config_path(config_path)
+                .with_capture(capture)
+                .with_fishing_error(fishing_error),
+            ))
         }),
     )
     .unwrap();
 
-    event_handler_thread.join().unwrap();
-
+    // the event handler runs until the process exits
     Ok(())
 }
 
+const LINE_COLORS: [Color32; 5] = [
+    Color32::from_rgb(200, 100, 100),
+    Color32::from_rgb(100, 150, 200),
+    Color32::from_rgb(120, 190, 110),
+    Color32::from_rgb(210, 170, 80),
+    Color32::from_rgb(170, 120, 200),
+];
+
 struct MyApp {
     events: Arc<Mutex<Vec<FroskEvent>>>,
-    correlations: Arc<Mutex<VecDeque<f32>>>,
+    correlations: Arc<Mutex<Vec<VecDeque<f32>>>>,
+    fishing_state: Arc<Mutex<FishingState>>,
+    /// Why the event handler stopped pressing keys
+    fishing_error: Arc<Mutex<Option<String>>>,
+    key_bindings: Arc<Mutex<KeyBindings>>,
+    /// Bindings shown in the key binding fields, to notice when the config is reloaded
+    shown_keys: KeyBindings,
+    /// Text of the key binding fields, which may not parse while being edited
+    key_inputs: [String; 2],
+    /// Where edited keys are saved
+    config_path: Option<PathBuf>,
+    /// Why the edited keys couldn't be saved
+    key_status: Option<String>,
+    template_names: Vec<String>,
+    retention: usize,
+    device_picker: DevicePicker,
+    /// Running capture, kept alive for as long as the window is open
+    capture: Option<CaptureHandle>,
     time: u32,
 }
 
+/// Picks the input device to capture from, which takes effect after a restart.
+struct DevicePicker {
+    names: Vec<String>,
+    selected: Option<usize>,
+    /// Where the picked device is saved
+    config_path: Option<PathBuf>,
+    /// Why capturing failed, or that the picked device needs a restart
+    status: Option<String>,
+}
+
+impl DevicePicker {
+    fn show(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Device", |ui| {
+            let mut selected = self.selected;
+            egui::ComboBox::from_id_salt("device")
+                .width(80.0)
+                .selected_text(selected.map_or("none", |i| self.names[i].as_str()))
+                .show_ui(ui, |ui| {
+                    for (i, name) in self.names.iter().enumerate() {
+                        ui.selectable_value(&mut selected, Some(i), name);
+                    }
+                });
+            if selected != self.selected {
+                self.selected = selected;
+                if let (Some(i), Some(path)) = (selected, &self.config_path) {
+                    let device = DeviceSelector::Name(self.names[i].clone());
+                    self.status = Some(match save_device(path, &device) {
+                        Ok(()) => "restart to apply".to_string(),
+                        Err(err) => format!("{err:#}"),
+                    });
+                }
+            }
+            if let Some(status) = &self.status {
+                ui.small(status);
+            }
+        });
+    }
+}
+
 impl MyApp {
-    fn new(events: Arc<Mutex<Vec<FroskEvent>>>, correlations: Arc<Mutex<VecDeque<f32>>>) -> Self {
+    fn new(
+        events: Arc<Mutex<Vec<FroskEvent>>>,
+        correlations: Arc<Mutex<Vec<VecDeque<f32>>>>,
+        fishing_state: Arc<Mutex<FishingState>>,
+        key_bindings: Arc<Mutex<KeyBindings>>,
+        template_names: Vec<String>,
+        retention: usize,
+        device_picker: DevicePicker,
+    ) -> Self {
+        let keys = *key_bindings.lock().unwrap();
         Self {
             events,
             correlations,
+            fishing_state,
+            fishing_error: Arc::default(),
+            key_bindings,
+            shown_keys: keys,
+            key_inputs: [keys.reel.to_string(), keys.cast.to_string()],
+            config_path: None,
+            key_status: None,
+            template_names,
+            retention,
+            device_picker,
+            capture: None,
             time: 0,
         }
     }
+
+    fn with_config_path(mut self, config_path: Option<PathBuf>) -> Self {
+        self.config_path = config_path;
+        self
+    }
+
+    fn with_capture(mut self, capture: Option<CaptureHandle>) -> Self {
+        self.capture = capture;
+        self
+    }
+
+    fn with_fishing_error(mut self, fishing_error: Arc<Mutex<Option<String>>>) -> Self {
+        self.fishing_error = fishing_error;
+        self
+    }
+
+    fn show_capture(&mut self, ui: &mut egui::Ui) {
+        let Some(capture) = &mut self.capture else {
+            return;
+        };
+        if let Some(err) = capture.errors().last() {
+            self.device_picker.status = Some(format!("{err:#}"));
+        }
+        let label = if capture.is_paused() {
+            "Resume"
+        } else {
+            "Pause"
+        };
+        if ui.small_button(label).clicked() {
+            let toggled = if capture.is_paused() {
+                capture.resume()
+            } else {
+                capture.pause()
+            };
+            if let Err(err) = toggled {
+                self.device_picker.status = Some(format!("{err:#}"));
+            }
+        }
+    }
 }
 
 impl eframe::App for MyApp {
@@ -116,7 +367,55 @@ impl eframe::App for MyApp {
             .show(ctx, |ui| {
                 ui.vertical_centered(|ui| {
                     ui.heading("Events");
+                    ui.small(format!("{:?}", self.fishing_state.lock().unwrap()));
+                    if let Some(err) = &*self.fishing_error.lock().unwrap() {
+                        ui.small(egui::RichText::new(err).color(Color32::RED));
+                    }
+                    self.show_capture(ui);
+                });
+
+                ui.collapsing("Keys", |ui| {
+                    let mut guard = self.key_bindings.lock().unwrap();
+                    let keys = &mut *guard;
+                    if *keys != self.shown_keys {
+                        self.key_inputs = [keys.reel.to_string(), keys.cast.to_string()];
+                    }
+                    let [reel_input, cast_input] = &mut self.key_inputs;
+                    let mut edited = false;
+                    for (label, input, binding) in [
+                        ("Reel", reel_input, &mut keys.reel),
+                        ("Cast", cast_input, &mut keys.cast),
+                    ] {
+                        ui.horizontal(|ui| {
+                            ui.label(label);
+                            let parsed = input.parse::<KeyBinding>();
+                            let color = if parsed.is_ok() {
+                                ui.visuals().text_color()
+                            } else {
+                                Color32::RED
+                            };
+                            let response = ui.add(
+                                egui::TextEdit::singleline(input)
+                                    .desired_width(50.0)
+                                    .text_color(color),
+                            );
+                            if response.changed() {
+                                if let Ok(parsed) = input.parse() {
+                                    *binding = parsed;
+                                    edited = true;
+                                }
+                            }
+                        });
+                    }
+                    if let (true, Some(path)) = (edited, &self.config_path) {
+                        self.key_status = save_keys(path, keys).err().map(|err| format!("{err:#}"));
+                    }
+                    if let Some(status) = &self.key_status {
+                        ui.small(status);
+                    }
+                    self.shown_keys = *keys;
                 });
+                self.device_picker.show(ui);
 
                 egui::ScrollArea::vertical()
                     .auto_shrink(false)
@@ -126,10 +425,13 @@ impl eframe::App for MyApp {
 
                         {
                             let events = self.events.lock().unwrap();
-                            events.iter().rev().for_each(|event| match event {
-                                FroskEvent::FishBite { score } => {
-                                    scroll_ui.label(format!("FishBite ({:.3})", score));
-                                }
+                            events.iter().rev().for_each(|event| {
+                                scroll_ui.label(match event.detection() {
+                                    Some(detection) => {
+                                        format!("{} ({:.3})", event.name(), detection.score)
+                                    }
+                                    None => event.name().to_string(),
+                                });
                             });
                         }
                     })
@@ -150,20 +452,26 @@ impl eframe::App for MyApp {
                 .show_axes(egui::Vec2b::new(false, false))
                 .auto_bounds(egui::Vec2b::new(true, true))
                 .include_x(0.0)
-                .include_x(RETENTION as f32)
+                .include_x(self.retention as f32)
                 .include_y(-1.0)
                 .include_y(1.0)
                 .show_grid(false);
 
             {
                 let correlations = self.correlations.lock().unwrap();
-                let (slice1, slice2) = correlations.as_slices();
-                let combined: Vec<f32> = slice1.iter().chain(slice2.iter()).cloned().collect();
                 my_plot.show(ui, |plot_ui| {
-                    let wave = Line::new(PlotPoints::from_ys_f32(&combined))
-                        .color(Color32::from_rgb(200, 100, 100))
-                        .style(egui_plot::LineStyle::Solid);
-                    plot_ui.line(wave);
+                    for (i, (history, name)) in
+                        correlations.iter().zip(&self.template_names).enumerate()
+                    {
+                        let (slice1, slice2) = history.as_slices();
+                        let combined: Vec<f32> =
+                            slice1.iter().chain(slice2.iter()).cloned().collect();
+                        let wave = Line::new(PlotPoints::from_ys_f32(&combined))
+                            .name(name)
+                            .color(LINE_COLORS[i % LINE_COLORS.len()])
+                            .style(egui_plot::LineStyle::Solid);
+                        plot_ui.line(wave);
+                    }
                 });
             }
         });
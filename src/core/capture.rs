@@ -1,41 +1,274 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::JoinHandle,
+    time::Instant,
+};
+
+use super::process::ProcessQuery;
+use device::DeviceSelector;
+use pulse::PulseSource;
+
+/// Sample rate live capture is resampled to by default, that of the bundled template.
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Where live audio comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaptureBackend {
+    /// The game's own output: process loopback on Windows, PulseAudio or
+    /// PipeWire on Linux and the input device on macOS, which has neither.
+    /// Other platforms have to use [`CaptureBackend::Device`].
+    #[default]
+    Game,
+    /// The input device picked by `device`, e.g. a loopback driver, on every platform.
+    Device,
+}
+
+/// Settings for capturing from a live device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    pub backend: CaptureBackend,
+    /// Input device to capture from with the device backend
+    pub device: DeviceSelector,
+    /// What to record on Linux with the game backend
+    pub pulse: PulseSource,
+    /// Game process, which is recorded on Windows and must have focus for keys to be pressed
+    pub process: ProcessQuery,
+    /// Frames per callback, if the device supports it
+    pub chunk_size: u32,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            backend: CaptureBackend::default(),
+            device: DeviceSelector::Name("BlackHole 2ch".to_string()),
+            pulse: PulseSource::default(),
+            process: ProcessQuery::default(),
+            chunk_size: file::DEFAULT_CHUNK_SIZE as u32,
+        }
+    }
+}
 
 pub trait AudioCapture {
-    /// # Safety
-    unsafe fn capture_game_audio(
+    /// Starts capturing, calling `callback` with every chunk of mono samples
+    /// along with the instant its last sample was captured. Capture runs until
+    /// the returned handle is stopped or dropped.
+    fn capture_game_audio(
         &self,
-        callback: impl FnMut(&[f32]) + Send + 'static,
-    ) -> Result<()>;
+        callback: impl FnMut(&[f32], Instant) + Send + 'static,
+    ) -> Result<CaptureHandle>;
 }
 
-pub fn default_audio_capture() -> impl AudioCapture {
-    #[cfg(target_os = "windows")]
-    {
-        windows::WindowsCapturer::default()
+/// What a capturer does when its [`CaptureHandle`] is used.
+pub trait CaptureControl {
+    fn pause(&mut self) -> Result<()>;
+
+    fn resume(&mut self) -> Result<()>;
+
+    /// Stops capturing and drops the callback, waiting for it to return if it is running.
+    fn stop(&mut self);
+
+    /// Whether capture ended on its own, e.g. at the end of a recording.
+    fn is_finished(&self) -> bool;
+}
+
+/// Owns a running capture, stopping it when dropped.
+pub struct CaptureHandle {
+    control: Box<dyn CaptureControl>,
+    errors: mpsc::Receiver<anyhow::Error>,
+    paused: bool,
+    stopped: bool,
+}
+
+impl CaptureHandle {
+    /// Wraps a running capture, whose errors are sent through `errors`.
+    pub fn new(
+        control: impl CaptureControl + 'static,
+        errors: mpsc::Receiver<anyhow::Error>,
+    ) -> Self {
+        Self {
+            control: Box::new(control),
+            errors,
+            paused: false,
+            stopped: false,
+        }
     }
-    #[cfg(target_os = "macos")]
-    {
-        macos::MacOsCapturer::default()
+
+    /// Stops delivering chunks until [`resume`](Self::resume) is called.
+    pub fn pause(&mut self) -> Result<()> {
+        if !self.paused && !self.stopped {
+            self.control.pause()?;
+            self.paused = true;
+        }
+        Ok(())
+    }
+
+    pub fn resume(&mut self) -> Result<()> {
+        if self.paused && !self.stopped {
+            self.control.resume()?;
+            self.paused = false;
+        }
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Stops capturing. Must not be called from the capture callback.
+    pub fn stop(mut self) {
+        self.stop_once();
+    }
+
+    /// Whether capture has ended, either stopped or on its own.
+    pub fn is_finished(&self) -> bool {
+        self.stopped || self.control.is_finished()
+    }
+
+    /// Errors reported since the last call, such as the device going away.
+    pub fn errors(&self) -> impl Iterator<Item = anyhow::Error> + '_ {
+        self.errors.try_iter()
+    }
+
+    fn stop_once(&mut self) {
+        if !self.stopped {
+            self.stopped = true;
+            self.control.stop();
+        }
+    }
+}
+
+impl Drop for CaptureHandle {
+    fn drop(&mut self) {
+        self.stop_once();
+    }
+}
+
+/// Pause and stop requests, polled by a capture thread between chunks.
+#[derive(Debug, Default)]
+pub struct CaptureFlags {
+    paused: AtomicBool,
+    stopped: AtomicBool,
+}
+
+impl CaptureFlags {
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+}
+
+/// Controls a capture running on its own thread.
+pub struct ThreadControl {
+    flags: Arc<CaptureFlags>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ThreadControl {
+    pub fn new(flags: Arc<CaptureFlags>, thread: JoinHandle<()>) -> Self {
+        Self {
+            flags,
+            thread: Some(thread),
+        }
     }
+}
+
+impl CaptureControl for ThreadControl {
+    fn pause(&mut self) -> Result<()> {
+        self.flags.paused.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.flags.paused.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.flags.stopped.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+}
+
+pub fn default_audio_capture() -> impl AudioCapture {
+    audio_capture(CaptureConfig::default(), SAMPLE_RATE)
+}
+
+/// Captures mono audio resampled to `sample_rate`, which should match the
+/// templates, with the backend picked in `config`.
+pub fn audio_capture(config: CaptureConfig, sample_rate: u32) -> impl AudioCapture {
+    match config.backend {
+        #[cfg(target_os = "windows")]
+        CaptureBackend::Game => Capturer::Game(windows::WindowsCapturer::new(config, sample_rate)),
+        #[cfg(target_os = "linux")]
+        CaptureBackend::Game => Capturer::Game(pulse::PulseCapturer::new(config, sample_rate)),
+        #[cfg(target_os = "macos")]
+        CaptureBackend::Game => Capturer::Device(cpal::DeviceCapturer::new(config, sample_rate)),
+        #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+        CaptureBackend::Game => Capturer::Unsupported,
+        CaptureBackend::Device => Capturer::Device(cpal::DeviceCapturer::new(config, sample_rate)),
+    }
+}
+
+/// The capturer of each backend.
+enum Capturer {
+    #[cfg(target_os = "windows")]
+    Game(windows::WindowsCapturer),
     #[cfg(target_os = "linux")]
-    {
-        // just use the macos capturer for now
-        macos::MacOsCapturer::default()
+    Game(pulse::PulseCapturer),
+    Device(cpal::DeviceCapturer),
+    /// Capturing the game's audio isn't implemented on this platform
+    #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+    Unsupported,
+}
+
+impl AudioCapture for Capturer {
+    fn capture_game_audio(
+        &self,
+        callback: impl FnMut(&[f32], Instant) + Send + 'static,
+    ) -> Result<CaptureHandle> {
+        match self {
+            #[cfg(any(target_os = "windows", target_os = "linux"))]
+            Capturer::Game(capturer) => capturer.capture_game_audio(callback),
+            Capturer::Device(capturer) => capturer.capture_game_audio(callback),
+            #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+            Capturer::Unsupported => {
+                anyhow::bail!("capturing the game's audio is not supported here, use a device")
+            }
+        }
     }
 }
 
 #[cfg(target_os = "windows")]
 pub mod windows {
-    use anyhow::Result;
+    use anyhow::{bail, Context, Result};
     use std::{
         ptr, slice,
-        sync::{Arc, Condvar, Mutex},
+        sync::{mpsc, Arc, Condvar, Mutex},
+        thread,
+        time::Instant,
     };
 
     use windows::{
         core::{implement, IUnknown, Interface, HRESULT, PCSTR, PROPVARIANT},
         Win32::{
-            Foundation::WAIT_OBJECT_0,
+            Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0, WAIT_TIMEOUT},
             Media::{
                 Audio::{
                     ActivateAudioInterfaceAsync, IActivateAudioInterfaceAsyncOperation,
@@ -49,18 +282,27 @@ pub mod windows {
                     VIRTUAL_AUDIO_DEVICE_PROCESS_LOOPBACK, WAVEFORMATEX, WAVEFORMATEXTENSIBLE,
                     WAVEFORMATEXTENSIBLE_0,
                 },
-                KernelStreaming::WAVE_FORMAT_EXTENSIBLE,
+                KernelStreaming::{SPEAKER_FRONT_CENTER, WAVE_FORMAT_EXTENSIBLE},
                 Multimedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
             },
             System::{
-                Com::{CoInitializeEx, COINIT_APARTMENTTHREADED},
+                Com::{CoInitializeEx, CoUninitialize, COINIT_MULTITHREADED},
                 Threading::{CreateEventA, WaitForSingleObject},
                 Variant::VT_BLOB,
             },
         },
     };
 
-    use super::AudioCapture;
+    use super::{
+        packet::{PacketDecoder, SILENT},
+        AudioCapture, CaptureConfig, CaptureFlags, CaptureHandle, ThreadControl,
+    };
+    use crate::core::process::{process_locator, ProcessLocator};
+
+    /// Buffer WASAPI keeps for us, in 100ns units
+    const BUFFER_DURATION: i64 = 200_000;
+    /// How long to wait for a packet before checking whether capture was stopped
+    const WAIT_MS: u32 = 100;
 
     #[implement(IActivateAudioInterfaceCompletionHandler)]
     struct Handler(Arc<(Mutex<bool>, Condvar)>);
@@ -85,214 +327,1535 @@ pub mod windows {
         }
     }
 
-    struct WindowsCapturer {}
+    /// Captures the audio of a single process and its children through WASAPI
+    /// process loopback, so no virtual cable is needed. Requires Windows 10 2004.
+    #[derive(Debug, Clone)]
+    pub struct WindowsCapturer {
+        config: CaptureConfig,
+        sample_rate: u32,
+        process_id: Option<u32>,
+    }
+
+    impl Default for WindowsCapturer {
+        fn default() -> Self {
+            Self::new(CaptureConfig::default(), super::SAMPLE_RATE)
+        }
+    }
+
+    impl WindowsCapturer {
+        /// Captures the process matching `config.process`, in mono at `sample_rate`.
+        pub fn new(config: CaptureConfig, sample_rate: u32) -> Self {
+            Self {
+                config,
+                sample_rate,
+                process_id: None,
+            }
+        }
+
+        /// Captures the process with this id instead of looking up the game window.
+        pub fn with_process_id(mut self, process_id: u32) -> Self {
+            self.process_id = Some(process_id);
+            self
+        }
+
+        fn target(&self) -> Result<u32> {
+            match self.process_id {
+                Some(process_id) => Ok(process_id),
+                None => Ok(process_locator()
+                    .find(&self.config.process)
+                    .context("looking for the game to capture")?
+                    .pid),
+            }
+        }
+    }
 
     impl AudioCapture for WindowsCapturer {
-        unsafe fn capture_game_audio(&self, mut callback: impl FnMut(&[f32]) -> ()) -> Result<()> {
-            let n_channels = 1;
-            let bits_per_sample = 32;
-            let sample_rate = 44100;
-
-            // Initialize COM
-            CoInitializeEx(None, COINIT_APARTMENTTHREADED).ok()?;
-
-            // Create audio client
-            let audio_client_activation_params = AUDIOCLIENT_ACTIVATION_PARAMS {
-                ActivationType: AUDIOCLIENT_ACTIVATION_TYPE_PROCESS_LOOPBACK,
-                Anonymous: AUDIOCLIENT_ACTIVATION_PARAMS_0 {
-                    ProcessLoopbackParams: AUDIOCLIENT_PROCESS_LOOPBACK_PARAMS {
-                        TargetProcessId: process_id,
-                        ProcessLoopbackMode: PROCESS_LOOPBACK_MODE_INCLUDE_TARGET_PROCESS_TREE,
-                    },
+        fn capture_game_audio(
+            &self,
+            callback: impl FnMut(&[f32], Instant) + Send + 'static,
+        ) -> Result<CaptureHandle> {
+            let process_id = self.target()?;
+            let sample_rate = self.sample_rate;
+            let flags = Arc::new(CaptureFlags::default());
+            let thread_flags = Arc::clone(&flags);
+            let (errors_tx, errors_rx) = mpsc::channel();
+            let (started_tx, started_rx) = mpsc::sync_channel(1);
+            let thread = thread::Builder::new()
+                .name("frosk-wasapi".to_string())
+                .spawn(move || {
+                    let mut stream = Stream {
+                        process_id,
+                        sample_rate,
+                        flags: &thread_flags,
+                        started: Some(started_tx),
+                        errors: &errors_tx,
+                    };
+                    // SAFETY: COM is initialised on this thread and every interface and
+                    // buffer used by `capture` stays on it, released before it returns
+                    let result = unsafe { capture(&mut stream, callback) };
+                    if let Err(err) = result {
+                        // errors before the stream started are returned from capture_game_audio
+                        let _ = match stream.started {
+                            Some(started) => started.send(Err(err)).is_ok(),
+                            None => errors_tx.send(err).is_ok(),
+                        };
+                    }
+                })?;
+            match started_rx.recv() {
+                Ok(Ok(())) => Ok(CaptureHandle::new(
+                    ThreadControl::new(flags, thread),
+                    errors_rx,
+                )),
+                Ok(Err(err)) => Err(err),
+                Err(_) => bail!("capture thread panicked while starting"),
+            }
+        }
+    }
+
+    /// What the capture thread needs besides the callback.
+    struct Stream<'a> {
+        process_id: u32,
+        sample_rate: u32,
+        flags: &'a CaptureFlags,
+        /// Taken once the stream is running
+        started: Option<mpsc::SyncSender<Result<()>>>,
+        errors: &'a mpsc::Sender<anyhow::Error>,
+    }
+
+    /// Uninitialises COM when capture ends, after all interfaces have been released.
+    struct ComGuard;
+
+    impl Drop for ComGuard {
+        fn drop(&mut self) {
+            unsafe { CoUninitialize() };
+        }
+    }
+
+    struct EventHandle(HANDLE);
+
+    impl Drop for EventHandle {
+        fn drop(&mut self) {
+            let _ = unsafe { CloseHandle(self.0) };
+        }
+    }
+
+    /// Captures the game on the current thread until it is stopped.
+    ///
+    /// # Safety
+    ///
+    /// Calls into COM and WASAPI, so it must run on a thread of its own that
+    /// nothing else initialises COM on.
+    unsafe fn capture(stream: &mut Stream, callback: impl FnMut(&[f32], Instant)) -> Result<()> {
+        // the activation callback arrives on another thread, which needs no marshalling in the MTA
+        CoInitializeEx(None, COINIT_MULTITHREADED).ok()?;
+        let _com = ComGuard;
+        let (audio_client, capture_client) = activate(stream.process_id, stream.sample_rate)?;
+
+        let event = EventHandle(CreateEventA(None, false, false, PCSTR::null())?);
+        audio_client.SetEventHandle(event.0)?;
+        audio_client.Start()?;
+        if let Some(started) = stream.started.take() {
+            let _ = started.send(Ok(()));
+        }
+
+        let result = read_packets(stream, &capture_client, &event, callback);
+        audio_client.Stop()?;
+        result
+    }
+
+    unsafe fn activate(
+        process_id: u32,
+        sample_rate: u32,
+    ) -> Result<(IAudioClient, IAudioCaptureClient)> {
+        let n_channels = 1;
+        let bits_per_sample = 32;
+
+        // Create audio client
+        let audio_client_activation_params = AUDIOCLIENT_ACTIVATION_PARAMS {
+            ActivationType: AUDIOCLIENT_ACTIVATION_TYPE_PROCESS_LOOPBACK,
+            Anonymous: AUDIOCLIENT_ACTIVATION_PARAMS_0 {
+                ProcessLoopbackParams: AUDIOCLIENT_PROCESS_LOOPBACK_PARAMS {
+                    TargetProcessId: process_id,
+                    ProcessLoopbackMode: PROCESS_LOOPBACK_MODE_INCLUDE_TARGET_PROCESS_TREE,
                 },
-            };
+            },
+        };
 
-            let raw_prop = windows_core::imp::PROPVARIANT {
-                Anonymous: windows_core::imp::PROPVARIANT_0 {
-                    Anonymous: windows_core::imp::PROPVARIANT_0_0 {
-                        vt: VT_BLOB.0,
-                        wReserved1: 0,
-                        wReserved2: 0,
-                        wReserved3: 0,
-                        Anonymous: windows_core::imp::PROPVARIANT_0_0_0 {
-                            blob: windows_core::imp::BLOB {
-                                cbSize: size_of::<AUDIOCLIENT_ACTIVATION_PARAMS>() as u32,
-                                pBlobData: &audio_client_activation_params as *const _ as *mut _,
-                            },
+        let raw_prop = windows_core::imp::PROPVARIANT {
+            Anonymous: windows_core::imp::PROPVARIANT_0 {
+                Anonymous: windows_core::imp::PROPVARIANT_0_0 {
+                    vt: VT_BLOB.0,
+                    wReserved1: 0,
+                    wReserved2: 0,
+                    wReserved3: 0,
+                    Anonymous: windows_core::imp::PROPVARIANT_0_0_0 {
+                        blob: windows_core::imp::BLOB {
+                            cbSize: size_of::<AUDIOCLIENT_ACTIVATION_PARAMS>() as u32,
+                            pBlobData: &audio_client_activation_params as *const _ as *mut _,
                         },
                     },
                 },
-            };
+            },
+        };
 
-            let activation_prop = PROPVARIANT::from_raw(raw_prop);
-            let activation_params = Some(&activation_prop as *const _);
-            let riid = IAudioClient::IID;
-
-            // Create completion handler
-            let setup = Arc::new((Mutex::new(false), Condvar::new()));
-            let completion_callback: IActivateAudioInterfaceCompletionHandler =
-                Handler::new(setup.clone()).into();
-
-            // Activate audio interface
-            let operation = ActivateAudioInterfaceAsync(
-                VIRTUAL_AUDIO_DEVICE_PROCESS_LOOPBACK,
-                &riid,
-                activation_params,
-                &completion_callback,
-            )?;
+        // the blob points into the activation params, so the variant must not free it
+        let activation_prop = std::mem::ManuallyDrop::new(PROPVARIANT::from_raw(raw_prop));
+        let activation_params = Some(&*activation_prop as *const _);
+        let riid = IAudioClient::IID;
 
-            // Wait for completion
-            let (lock, cvar) = &*setup;
-            let mut completed = lock.lock().unwrap();
-            while !*completed {
-                completed = cvar.wait(completed).unwrap();
+        // Create completion handler
+        let setup = Arc::new((Mutex::new(false), Condvar::new()));
+        let completion_callback: IActivateAudioInterfaceCompletionHandler =
+            Handler::new(setup.clone()).into();
+
+        // Activate audio interface
+        let operation = ActivateAudioInterfaceAsync(
+            VIRTUAL_AUDIO_DEVICE_PROCESS_LOOPBACK,
+            &riid,
+            activation_params,
+            &completion_callback,
+        )?;
+
+        // Wait for completion
+        let (lock, cvar) = &*setup;
+        let mut completed = lock.lock().unwrap();
+        while !*completed {
+            completed = cvar.wait(completed).unwrap();
+        }
+        drop(completed);
+
+        // Get audio client and result
+        let mut audio_client: Option<IUnknown> = Default::default();
+        let mut result: HRESULT = Default::default();
+        operation.GetActivateResult(&mut result, &mut audio_client)?;
+
+        // Ensure successful activation
+        result
+            .ok()
+            .with_context(|| format!("activating loopback capture of process {process_id}"))?;
+        let Some(audio_client) = audio_client else {
+            bail!("activating loopback capture of process {process_id} returned no client");
+        };
+        let audio_client: IAudioClient = audio_client.cast()?;
+
+        // Audio client arguments
+        let block_align = n_channels * bits_per_sample / 8;
+        let byte_rate = sample_rate * block_align;
+
+        let extensible = WAVEFORMATEXTENSIBLE {
+            Format: WAVEFORMATEX {
+                wFormatTag: WAVE_FORMAT_EXTENSIBLE as u16,
+                nChannels: n_channels as u16,
+                nSamplesPerSec: sample_rate,
+                nAvgBytesPerSec: byte_rate,
+                nBlockAlign: block_align as u16,
+                wBitsPerSample: bits_per_sample as u16,
+                cbSize: (size_of::<WAVEFORMATEXTENSIBLE>() - size_of::<WAVEFORMATEX>()) as u16,
+            },
+            Samples: WAVEFORMATEXTENSIBLE_0 {
+                wValidBitsPerSample: bits_per_sample as u16,
+            },
+            SubFormat: KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
+            dwChannelMask: SPEAKER_FRONT_CENTER,
+        };
+
+        let stream_flags = AUDCLNT_STREAMFLAGS_EVENTCALLBACK | AUDCLNT_STREAMFLAGS_LOOPBACK;
+
+        // Initialise audio client
+        audio_client.Initialize(
+            AUDCLNT_SHAREMODE_SHARED,
+            stream_flags,
+            BUFFER_DURATION,
+            0,
+            &extensible.Format,
+            None,
+        )?;
+
+        // Get capture client
+        let capture_client = audio_client.GetService::<IAudioCaptureClient>()?;
+        Ok((audio_client, capture_client))
+    }
+
+    /// Waits for packets and hands them to `callback` until capture is stopped.
+    unsafe fn read_packets(
+        stream: &Stream,
+        capture_client: &IAudioCaptureClient,
+        event: &EventHandle,
+        mut callback: impl FnMut(&[f32], Instant),
+    ) -> Result<()> {
+        let mut decoder = PacketDecoder::new(1);
+        while !stream.flags.is_stopped() {
+            // the event is only signalled while the game plays sound, so waits time out in silence
+            match WaitForSingleObject(event.0, WAIT_MS) {
+                WAIT_OBJECT_0 | WAIT_TIMEOUT => {}
+                other => bail!("waiting for captured audio failed with {:#x}", other.0),
             }
-            drop(completed);
 
-            // Get audio client and result
-            let mut audio_client: Option<IUnknown> = Default::default();
-            let mut result: HRESULT = Default::default();
-            operation.GetActivateResult(&mut result, &mut audio_client)?;
-
-            // Ensure successful activation
-            result.ok()?;
-            let audio_client: IAudioClient = audio_client.unwrap().cast()?;
-
-            // Audio client arguments
-            let block_align = n_channels * bits_per_sample / 8;
-            let byte_rate = sample_rate * block_align;
-
-            let extensible = WAVEFORMATEXTENSIBLE {
-                Format: WAVEFORMATEX {
-                    wFormatTag: WAVE_FORMAT_EXTENSIBLE as u16,
-                    nChannels: 1,
-                    nSamplesPerSec: sample_rate,
-                    nAvgBytesPerSec: byte_rate,
-                    nBlockAlign: block_align as u16,
-                    wBitsPerSample: bits_per_sample as u16,
-                    cbSize: (size_of::<WAVEFORMATEXTENSIBLE>() - size_of::<WAVEFORMATEX>()) as u16,
-                },
-                Samples: WAVEFORMATEXTENSIBLE_0 {
-                    wValidBitsPerSample: 32,
-                },
-                SubFormat: KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
-                dwChannelMask: 0x1 | 0x2,
-            };
+            // a single event may cover several packets
+            while capture_client.GetNextPacketSize()? > 0 {
+                let mut data = ptr::null_mut();
+                let mut frames = 0;
+                let mut buffer_flags = 0;
+                capture_client.GetBuffer(&mut data, &mut frames, &mut buffer_flags, None, None)?;
+
+                // silent packets may not point at anything worth reading
+                let bytes: &[u8] = if buffer_flags & SILENT != 0 || data.is_null() {
+                    &[]
+                } else {
+                    slice::from_raw_parts(data, frames as usize * decoder.frame_size())
+                };
+                let packet = decoder.decode(bytes, frames as usize, buffer_flags);
+                if packet.discontinuity {
+                    let _ = stream.errors.send(anyhow::anyhow!(
+                        "captured audio has a gap, samples were lost"
+                    ));
+                }
+                if !stream.flags.is_paused() && !packet.samples.is_empty() {
+                    callback(packet.samples, Instant::now());
+                }
 
-            let stream_flags = AUDCLNT_STREAMFLAGS_EVENTCALLBACK | AUDCLNT_STREAMFLAGS_LOOPBACK;
+                capture_client.ReleaseBuffer(frames)?;
+            }
+        }
+        Ok(())
+    }
+}
 
-            // Initialise audio client
-            audio_client.Initialize(
-                AUDCLNT_SHAREMODE_SHARED,
-                stream_flags,
-                200000,
-                0,
-                &extensible.Format,
-                None,
-            )?;
+/// Turns the packets of the Windows capturer into mono samples, kept free of
+/// Windows APIs so it can be tested anywhere.
+pub mod packet {
+    /// `AUDCLNT_BUFFERFLAGS_DATA_DISCONTINUITY`, there is a gap before this packet.
+    pub const DATA_DISCONTINUITY: u32 = 0x1;
+    /// `AUDCLNT_BUFFERFLAGS_SILENT`, the packet is silence whatever its data says.
+    pub const SILENT: u32 = 0x2;
 
-            // Get capture client
-            let capture_client = audio_client.GetService::<IAudioCaptureClient>()?;
+    /// Decodes interleaved little-endian float frames, averaging the channels.
+    /// A trailing partial frame is ignored.
+    pub fn decode_f32le(bytes: &[u8], channels: u16, output: &mut Vec<f32>) {
+        let channels = channels.max(1) as usize;
+        output.extend(bytes.chunks_exact(4 * channels).map(|frame| {
+            frame
+                .chunks_exact(4)
+                .map(|sample| f32::from_le_bytes(sample.try_into().unwrap()))
+                .sum::<f32>()
+                / channels as f32
+        }));
+    }
 
-            // Set event handle
-            let h_event = CreateEventA(None, false, false, PCSTR::null())?;
-            audio_client.SetEventHandle(h_event)?;
-            audio_client.Start()?;
+    #[derive(Debug, PartialEq)]
+    pub struct Packet<'a> {
+        pub samples: &'a [f32],
+        /// Samples were lost between the previous packet and this one
+        pub discontinuity: bool,
+    }
+
+    /// Decodes packets of 32-bit float frames as returned by `IAudioCaptureClient::GetBuffer`.
+    #[derive(Debug)]
+    pub struct PacketDecoder {
+        channels: u16,
+        samples: Vec<f32>,
+        first: bool,
+    }
+
+    impl PacketDecoder {
+        pub fn new(channels: u16) -> Self {
+            Self {
+                channels: channels.max(1),
+                samples: Vec::new(),
+                first: true,
+            }
+        }
+
+        /// Bytes in one frame of the packet data.
+        pub fn frame_size(&self) -> usize {
+            4 * self.channels as usize
+        }
+
+        /// Decodes a packet of `frames` frames, given its data and buffer flags.
+        /// Silent packets become zeros without looking at `data`.
+        pub fn decode(&mut self, data: &[u8], frames: usize, flags: u32) -> Packet<'_> {
+            self.samples.clear();
+            if flags & SILENT != 0 {
+                self.samples.resize(frames, 0.0);
+            } else {
+                let len = (frames * self.frame_size()).min(data.len());
+                decode_f32le(&data[..len], self.channels, &mut self.samples);
+            }
+            // the first packet after starting is flagged as there is nothing before it
+            let discontinuity = flags & DATA_DISCONTINUITY != 0 && !self.first;
+            self.first = false;
+            Packet {
+                samples: &self.samples,
+                discontinuity,
+            }
+        }
+    }
 
-            loop {
-                let frames_available = capture_client.GetNextPacketSize()?;
-                if frames_available < 1 {
-                    continue;
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn bytes(samples: &[f32]) -> Vec<u8> {
+            samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+        }
+
+        #[test]
+        fn test_decode_f32le_averages_channels() {
+            let mut output = Vec::new();
+            decode_f32le(&bytes(&[0.5, 0.25, -1.0]), 1, &mut output);
+            assert_eq!(output, [0.5, 0.25, -1.0]);
+
+            output.clear();
+            // the last half frame is dropped
+            decode_f32le(&bytes(&[0.5, 0.25, -1.0, 0.0, 0.75]), 2, &mut output);
+            assert_eq!(output, [0.375, -0.5]);
+        }
+
+        #[test]
+        fn test_decoder_handles_buffer_flags() {
+            let mut decoder = PacketDecoder::new(1);
+            let data = bytes(&[0.5, -0.5, 0.25]);
+
+            let first = decoder.decode(&data, 3, DATA_DISCONTINUITY);
+            assert_eq!(first.samples, [0.5, -0.5, 0.25]);
+            assert!(!first.discontinuity);
+
+            let silent = decoder.decode(&[], 4, SILENT);
+            assert_eq!(silent.samples, [0.0; 4]);
+            assert!(!silent.discontinuity);
+
+            let gap = decoder.decode(&data, 2, DATA_DISCONTINUITY | SILENT);
+            assert_eq!(gap.samples, [0.0; 2]);
+            assert!(gap.discontinuity);
+
+            // a short buffer is decoded as far as it goes
+            assert_eq!(decoder.decode(&data[..8], 3, 0).samples, [0.5, -0.5]);
+        }
+    }
+}
+
+/// Captures from an input device with cpal, which works on every platform and
+/// is the only backend on macOS.
+pub mod cpal {
+    use super::{
+        device::find_input_device, resample::Converter, AudioCapture, CaptureConfig,
+        CaptureControl, CaptureHandle,
+    };
+    use anyhow::{bail, Result};
+    use cpal::{
+        traits::{DeviceTrait, StreamTrait},
+        FromSample, SampleFormat, SizedSample,
+    };
+    use std::{
+        sync::mpsc,
+        time::{Duration, Instant},
+    };
+
+    /// Captures from an input device in whatever format it prefers, delivering
+    /// mono audio at `sample_rate`.
+    pub struct DeviceCapturer {
+        config: CaptureConfig,
+        sample_rate: u32,
+    }
+
+    impl Default for DeviceCapturer {
+        fn default() -> Self {
+            Self::new(CaptureConfig::default(), super::SAMPLE_RATE)
+        }
+    }
+
+    impl DeviceCapturer {
+        pub fn new(config: CaptureConfig, sample_rate: u32) -> Self {
+            Self {
+                config,
+                sample_rate,
+            }
+        }
+    }
+
+    impl AudioCapture for DeviceCapturer {
+        fn capture_game_audio(
+            &self,
+            callback: impl FnMut(&[f32], Instant) + Send + 'static,
+        ) -> Result<CaptureHandle> {
+            let loopback_device = find_input_device(&self.config.device)?;
+            let supported = loopback_device.default_input_config()?;
+            // the requested chunk size is only a preference, as not every device supports it
+            let buffer_size = match supported.buffer_size() {
+                cpal::SupportedBufferSize::Range { min, max }
+                    if (*min..=*max).contains(&self.config.chunk_size) =>
+                {
+                    cpal::BufferSize::Fixed(self.config.chunk_size)
                 }
+                _ => cpal::BufferSize::Default,
+            };
+            let config = cpal::StreamConfig {
+                channels: supported.channels(),
+                sample_rate: supported.sample_rate(),
+                buffer_size,
+            };
 
-                // Get pointer to buffer
-                let mut buffer_ptr = ptr::null_mut();
-                let mut nbr_frames_returned = 0;
-                let mut flags = 0;
-                capture_client.GetBuffer(
-                    &mut buffer_ptr,
-                    &mut nbr_frames_returned,
-                    &mut flags,
-                    None,
-                    None,
-                )?;
-
-                // Fill buffer
-                let len_in_bytes = nbr_frames_returned as usize * block_align as usize;
-                let bufferslice = slice::from_raw_parts(buffer_ptr, len_in_bytes);
-
-                // bytes are little endian
-                let mut audio_data = Vec::with_capacity(bufferslice.len() / block_align as usize);
-                for i in (0..bufferslice.len()).step_by(4) {
-                    let sample = f32::from_le_bytes([
-                        bufferslice[i],
-                        bufferslice[i + 1],
-                        bufferslice[i + 2],
-                        bufferslice[i + 3],
-                    ]);
-                    audio_data.push(sample);
+            let (errors_tx, errors_rx) = mpsc::channel();
+            let device = &loopback_device;
+            let rate = self.sample_rate;
+            let stream = match supported.sample_format() {
+                SampleFormat::F32 => {
+                    build_stream::<f32>(device, &config, rate, callback, errors_tx)
+                }
+                SampleFormat::I16 => {
+                    build_stream::<i16>(device, &config, rate, callback, errors_tx)
+                }
+                SampleFormat::I32 => {
+                    build_stream::<i32>(device, &config, rate, callback, errors_tx)
                 }
-                callback(&audio_data);
+                SampleFormat::U16 => {
+                    build_stream::<u16>(device, &config, rate, callback, errors_tx)
+                }
+                format => bail!("unsupported sample format {format}"),
+            }?;
+
+            stream.play()?;
+
+            Ok(CaptureHandle::new(StreamControl(Some(stream)), errors_rx))
+        }
+    }
+
+    /// Keeps a cpal stream alive, as dropping it ends capture.
+    struct StreamControl(Option<cpal::Stream>);
+
+    impl CaptureControl for StreamControl {
+        fn pause(&mut self) -> Result<()> {
+            if let Some(stream) = &self.0 {
+                stream.pause()?;
+            }
+            Ok(())
+        }
+
+        fn resume(&mut self) -> Result<()> {
+            if let Some(stream) = &self.0 {
+                stream.play()?;
+            }
+            Ok(())
+        }
+
+        fn stop(&mut self) {
+            self.0 = None;
+        }
+
+        fn is_finished(&self) -> bool {
+            self.0.is_none()
+        }
+    }
 
-                // Release buffer
-                if nbr_frames_returned > 0 {
-                    capture_client.ReleaseBuffer(nbr_frames_returned).unwrap();
+    fn build_stream<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        sample_rate: u32,
+        mut callback: impl FnMut(&[f32], Instant) + Send + 'static,
+        errors: mpsc::Sender<anyhow::Error>,
+    ) -> Result<cpal::Stream>
+    where
+        T: SizedSample,
+        f32: FromSample<T>,
+    {
+        let device_rate = config.sample_rate.0;
+        let channels = config.channels;
+        let mut converter = Converter::new(channels, device_rate, sample_rate);
+        let mut interleaved = Vec::new();
+        let stream = device.build_input_stream(
+            config,
+            move |chunk: &[T], info: &cpal::InputCallbackInfo| {
+                // the host reports when the first sample was captured, relative to this callback
+                let timestamp = info.timestamp();
+                let delay = timestamp
+                    .callback
+                    .duration_since(&timestamp.capture)
+                    .unwrap_or_default();
+                let frames = chunk.len() / channels as usize;
+                let duration = Duration::from_secs_f64(frames as f64 / device_rate as f64);
+                let now = Instant::now();
+
+                interleaved.clear();
+                interleaved.extend(chunk.iter().map(|sample| sample.to_sample::<f32>()));
+                let mono = converter.process(&interleaved);
+                if !mono.is_empty() {
+                    callback(mono, (now - delay + duration).min(now));
                 }
+            },
+            move |err| {
+                // nobody is listening once the handle is dropped
+                let _ = errors.send(err.into());
+            },
+            None,
+        )?;
+        Ok(stream)
+    }
+}
+
+pub mod resample {
+    /// Resamples a stream chunk by chunk using linear interpolation.
+    #[derive(Debug, Clone)]
+    pub struct Resampler {
+        /// Input samples advanced per output sample
+        step: f64,
+        /// Input position of the next output sample, relative to the start of the
+        /// next chunk. -1 refers to the last sample of the previous chunk.
+        position: f64,
+        last: f32,
+    }
+
+    impl Resampler {
+        pub fn new(from_rate: u32, to_rate: u32) -> Self {
+            Self {
+                step: from_rate as f64 / to_rate as f64,
+                position: 0.0,
+                last: 0.0,
+            }
+        }
+
+        pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+            let Some(&last) = input.last() else {
+                return;
+            };
+            let end = (input.len() - 1) as f64;
+            while self.position < end {
+                let index = self.position.floor();
+                let fraction = (self.position - index) as f32;
+                let a = if index < 0.0 {
+                    self.last
+                } else {
+                    input[index as usize]
+                };
+                let b = input[(index + 1.0) as usize];
+                output.push(a + (b - a) * fraction);
+                self.position += self.step;
+            }
+            self.position -= input.len() as f64;
+            self.last = last;
+        }
+    }
+
+    /// Turns interleaved device audio into mono at the sample rate the templates use.
+    #[derive(Debug, Clone)]
+    pub struct Converter {
+        channels: usize,
+        /// Only needed when the rates differ
+        resampler: Option<Resampler>,
+        mono: Vec<f32>,
+        output: Vec<f32>,
+    }
+
+    impl Converter {
+        pub fn new(channels: u16, from_rate: u32, to_rate: u32) -> Self {
+            Self {
+                channels: channels.max(1) as usize,
+                resampler: (from_rate != to_rate).then(|| Resampler::new(from_rate, to_rate)),
+                mono: Vec::new(),
+                output: Vec::new(),
+            }
+        }
 
-                // Read from device to queue
-                let retval = WaitForSingleObject(h_event, 100000);
-                if retval.0 != WAIT_OBJECT_0.0 {
-                    panic!("AHHHHH");
+        /// Converts a chunk of whole frames, returning the samples it yields.
+        pub fn process(&mut self, interleaved: &[f32]) -> &[f32] {
+            self.mono.clear();
+            self.mono.extend(
+                interleaved
+                    .chunks_exact(self.channels)
+                    .map(|frame| frame.iter().sum::<f32>() / self.channels as f32),
+            );
+            match &mut self.resampler {
+                Some(resampler) => {
+                    self.output.clear();
+                    resampler.process(&self.mono, &mut self.output);
+                    &self.output
                 }
+                None => &self.mono,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::f32::consts::TAU;
+
+        fn sine(frequency: f32, sample_rate: u32, len: usize) -> Vec<f32> {
+            (0..len)
+                .map(|i| (TAU * frequency * i as f32 / sample_rate as f32).sin())
+                .collect()
+        }
+
+        #[test]
+        fn test_resampler_streams_like_one_pass() {
+            let input = sine(440.0, 48000, 48000);
+            let mut whole = Vec::new();
+            Resampler::new(48000, 44100).process(&input, &mut whole);
+            // the output lags one input sample behind, so the count is off by at most one
+            assert!((whole.len() as i64 - 44100).abs() <= 1);
 
-                // we can sleep for about 10ms
-                std::thread::sleep(std::time::Duration::from_millis(10));
+            let mut resampler = Resampler::new(48000, 44100);
+            let mut streamed = Vec::new();
+            for chunk in input.chunks(441) {
+                resampler.process(chunk, &mut streamed);
+            }
+            assert_eq!(streamed.len(), whole.len());
+            for (a, b) in streamed.iter().zip(&whole) {
+                assert!((a - b).abs() < 1e-4);
             }
 
-            Ok(())
+            let expected = sine(440.0, 44100, whole.len());
+            for (a, b) in whole.iter().zip(&expected) {
+                assert!((a - b).abs() < 1e-2, "{a} vs {b}");
+            }
+        }
+
+        #[test]
+        fn test_converter_downmixes_and_passes_through_matching_rate() {
+            let mut converter = Converter::new(2, 44100, 44100);
+            assert_eq!(converter.process(&[1.0, 0.0, -0.5, -0.5]), [0.5, -0.5]);
+
+            let mut converter = Converter::new(2, 88200, 44100);
+            let interleaved: Vec<f32> = (0..8).flat_map(|i| [i as f32, i as f32]).collect();
+            assert_eq!(converter.process(&interleaved), [0.0, 2.0, 4.0, 6.0]);
         }
     }
 }
 
-#[cfg(any(target_os = "macos", target_os = "linux"))]
-pub mod macos {
-    use super::AudioCapture;
-    use anyhow::Result;
-    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+/// Records through the PulseAudio tools, which also work with PipeWire, so that
+/// the game can be captured when running under Wine or Proton.
+pub mod pulse {
+    use super::{
+        AudioCapture, CaptureConfig, CaptureControl, CaptureFlags, CaptureHandle, ThreadControl,
+    };
+    use anyhow::{anyhow, bail, Context, Result};
+    use serde::{Deserialize, Serialize};
+    use std::{
+        collections::HashMap,
+        io::Read,
+        process::{Child, Command, Stdio},
+        sync::{atomic::Ordering, mpsc, Arc},
+        thread,
+        time::Instant,
+    };
+
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum PulseSource {
+        /// The monitor of the default output, which includes every application
+        #[default]
+        DefaultMonitor,
+        /// A source by name, e.g. `frosk.monitor` for the monitor of a null sink named `frosk`
+        Source(String),
+        /// Only the audio of the application whose name or binary contains this,
+        /// e.g. `Wow.exe`
+        Application(String),
+    }
+
+    /// An application playing audio, as listed by `pactl list sink-inputs`.
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct SinkInput {
+        pub index: u32,
+        pub properties: HashMap<String, String>,
+    }
+
+    impl SinkInput {
+        pub fn application_name(&self) -> Option<&str> {
+            self.properties.get("application.name").map(String::as_str)
+        }
+
+        pub fn binary(&self) -> Option<&str> {
+            self.properties
+                .get("application.process.binary")
+                .map(String::as_str)
+        }
+
+        fn matches(&self, application: &str) -> bool {
+            let application = application.to_lowercase();
+            [self.application_name(), self.binary()]
+                .into_iter()
+                .flatten()
+                .any(|name| name.to_lowercase().contains(&application))
+        }
+    }
+
+    /// Parses the output of `pactl list sink-inputs`, which must use the C locale.
+    pub fn parse_sink_inputs(output: &str) -> Vec<SinkInput> {
+        let mut sink_inputs: Vec<SinkInput> = Vec::new();
+        for line in output.lines() {
+            if let Some(index) = line.strip_prefix("Sink Input #") {
+                if let Ok(index) = index.trim().parse() {
+                    sink_inputs.push(SinkInput {
+                        index,
+                        ..SinkInput::default()
+                    });
+                }
+                continue;
+            }
+            // properties are the only lines of the form `key = "value"`
+            let (Some(sink_input), Some((key, value))) =
+                (sink_inputs.last_mut(), line.split_once(" = "))
+            else {
+                continue;
+            };
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            sink_input
+                .properties
+                .insert(key.trim().to_string(), value.to_string());
+        }
+        sink_inputs
+    }
 
+    /// Lists the applications currently playing audio.
+    pub fn sink_inputs() -> Result<Vec<SinkInput>> {
+        let output = Command::new("pactl")
+            .args(["list", "sink-inputs"])
+            .env("LC_ALL", "C")
+            .output()
+            .context("failed to run pactl, is pulseaudio-utils installed?")?;
+        if !output.status.success() {
+            bail!(
+                "pactl failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(parse_sink_inputs(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Arguments for `parec` recording mono floats at `sample_rate`, which the
+    /// sound server resamples to.
+    pub fn parec_args(
+        source: &PulseSource,
+        sink_inputs: &[SinkInput],
+        sample_rate: u32,
+        chunk_size: u32,
+    ) -> Result<Vec<String>> {
+        let mut args = vec![
+            "--raw".to_string(),
+            "--format=float32le".to_string(),
+            "--channels=1".to_string(),
+            format!("--rate={sample_rate}"),
+            format!("--latency={}", chunk_size * 4),
+        ];
+        match source {
+            PulseSource::DefaultMonitor => args.push("--device=@DEFAULT_MONITOR@".to_string()),
+            PulseSource::Source(name) => args.push(format!("--device={name}")),
+            PulseSource::Application(application) => {
+                let Some(sink_input) = sink_inputs.iter().find(|s| s.matches(application)) else {
+                    let playing: Vec<&str> = sink_inputs
+                        .iter()
+                        .filter_map(|s| s.application_name())
+                        .collect();
+                    bail!(
+                        "no application matching {application:?} is playing audio, playing: {}",
+                        playing.join(", ")
+                    );
+                };
+                args.push(format!("--monitor-stream={}", sink_input.index));
+            }
+        }
+        Ok(args)
+    }
+
+    /// Records with `parec` on a background thread.
     #[derive(Default)]
-    pub struct MacOsCapturer {}
+    pub struct PulseCapturer {
+        config: CaptureConfig,
+        sample_rate: u32,
+    }
 
-    impl AudioCapture for MacOsCapturer {
-        unsafe fn capture_game_audio(
+    impl PulseCapturer {
+        pub fn new(config: CaptureConfig, sample_rate: u32) -> Self {
+            Self {
+                config,
+                sample_rate,
+            }
+        }
+    }
+
+    impl AudioCapture for PulseCapturer {
+        fn capture_game_audio(
             &self,
-            mut callback: impl FnMut(&[f32]) + Send + 'static,
-        ) -> Result<()> {
-            let host = cpal::default_host();
-            let loopback_device = host
-                .input_devices()?
-                .find(|d| d.name().unwrap().contains("BlackHole 2ch"))
-                .unwrap();
-            let config = cpal::StreamConfig {
-                channels: 1,
-                sample_rate: cpal::SampleRate(44100),
-                buffer_size: cpal::BufferSize::Fixed(440),
+            mut callback: impl FnMut(&[f32], Instant) + Send + 'static,
+        ) -> Result<CaptureHandle> {
+            let sink_inputs = match self.config.pulse {
+                PulseSource::Application(_) => sink_inputs()?,
+                _ => Vec::new(),
             };
+            let args = parec_args(
+                &self.config.pulse,
+                &sink_inputs,
+                self.sample_rate,
+                self.config.chunk_size,
+            )?;
+            let mut child = Command::new("parec")
+                .args(&args)
+                .stdout(Stdio::piped())
+                .spawn()
+                .context("failed to run parec, is pulseaudio-utils installed?")?;
+            let mut stdout = child.stdout.take().unwrap();
+            let chunk_size = self.config.chunk_size.max(1) as usize;
+            let flags = Arc::new(CaptureFlags::default());
+            let thread_flags = Arc::clone(&flags);
+            let (errors_tx, errors_rx) = mpsc::channel();
 
-            let stream = loopback_device.build_input_stream(
-                &config,
-                move |chunk: &[f32], _: &_| {
-                    callback(chunk);
-                },
-                move |err| {
-                    eprintln!("an error occurred on stream: {}", err);
+            let thread = thread::Builder::new()
+                .name("frosk-parec".to_string())
+                .spawn(move || {
+                    let mut bytes = vec![0; chunk_size * 4];
+                    let mut chunk = vec![0.0; chunk_size];
+                    // parec exits when the source goes away, ending the stream
+                    while stdout.read_exact(&mut bytes).is_ok() {
+                        if thread_flags.is_stopped() {
+                            return;
+                        }
+                        // parec keeps recording while paused, so it never blocks on a full pipe
+                        if thread_flags.is_paused() {
+                            continue;
+                        }
+                        for (sample, le) in chunk.iter_mut().zip(bytes.chunks_exact(4)) {
+                            *sample = f32::from_le_bytes(le.try_into().unwrap());
+                        }
+                        callback(&chunk, Instant::now());
+                    }
+                    if !thread_flags.is_stopped() {
+                        let _ = errors_tx.send(anyhow!("parec stopped recording"));
+                    }
+                })?;
+            Ok(CaptureHandle::new(
+                ParecControl {
+                    child,
+                    thread: ThreadControl::new(flags, thread),
                 },
-                None,
-            )?;
+                errors_rx,
+            ))
+        }
+    }
 
-            stream.play()?;
+    /// Owns the `parec` process, killing it to unblock the reading thread.
+    struct ParecControl {
+        child: Child,
+        thread: ThreadControl,
+    }
 
-            Ok(())
+    impl CaptureControl for ParecControl {
+        fn pause(&mut self) -> Result<()> {
+            self.thread.pause()
+        }
+
+        fn resume(&mut self) -> Result<()> {
+            self.thread.resume()
+        }
+
+        fn stop(&mut self) {
+            self.thread.flags.stopped.store(true, Ordering::Relaxed);
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+            self.thread.stop();
+        }
+
+        fn is_finished(&self) -> bool {
+            self.thread.is_finished()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const SINK_INPUTS: &str = r#"Sink Input #42
+	Driver: protocol-native.c
+	Owner Module: 9
+	Client: 57
+	Sink: 0
+	Sample Specification: float32le 2ch 48000Hz
+	Mute: no
+	Volume: front-left: 65536 / 100% / 0.00 dB,   front-right: 65536 / 100% / 0.00 dB
+	Properties:
+		media.name = "audio stream #1"
+		application.name = "World of Warcraft"
+		application.process.binary = "Wow.exe"
+		application.process.id = "1234"
+
+Sink Input #57
+	Driver: PipeWire
+	Properties:
+		application.name = "Firefox"
+		application.process.binary = "firefox"
+"#;
+
+        #[test]
+        fn test_parse_sink_inputs() {
+            let sink_inputs = parse_sink_inputs(SINK_INPUTS);
+            assert_eq!(sink_inputs.len(), 2);
+            assert_eq!(sink_inputs[0].index, 42);
+            assert_eq!(sink_inputs[0].application_name(), Some("World of Warcraft"));
+            assert_eq!(sink_inputs[0].binary(), Some("Wow.exe"));
+            assert_eq!(sink_inputs[0].properties["media.name"], "audio stream #1");
+            assert_eq!(sink_inputs[1].binary(), Some("firefox"));
+        }
+
+        #[test]
+        fn test_parec_args_pick_source() {
+            let sink_inputs = parse_sink_inputs(SINK_INPUTS);
+            let wow = PulseSource::Application("wow.exe".to_string());
+            let args = parec_args(&wow, &sink_inputs, 44100, 440).unwrap();
+            assert!(args.contains(&"--monitor-stream=42".to_string()));
+            assert!(args.contains(&"--rate=44100".to_string()));
+
+            let source = PulseSource::Source("frosk.monitor".to_string());
+            let args = parec_args(&source, &[], 44100, 440).unwrap();
+            assert!(args.contains(&"--device=frosk.monitor".to_string()));
+
+            let missing = PulseSource::Application("Diablo".to_string());
+            let err = parec_args(&missing, &sink_inputs, 44100, 440).unwrap_err();
+            assert!(err.to_string().contains("World of Warcraft, Firefox"));
+        }
+
+        /// Plays a tone into a null sink and records its monitor. Needs a
+        /// PulseAudio or PipeWire server along with pactl, pacat and parec.
+        #[test]
+        #[ignore = "needs a running sound server"]
+        fn test_records_null_sink_monitor() {
+            let module = Command::new("pactl")
+                .args(["load-module", "module-null-sink", "sink_name=frosk_test"])
+                .output()
+                .unwrap();
+            assert!(module.status.success());
+            let module = String::from_utf8(module.stdout).unwrap();
+
+            let config = CaptureConfig {
+                pulse: PulseSource::Source("frosk_test.monitor".to_string()),
+                ..CaptureConfig::default()
+            };
+            let (tx, rx) = std::sync::mpsc::channel();
+            let _capture = PulseCapturer::new(config, 44100)
+                .capture_game_audio(move |chunk, _| {
+                    let _ = tx.send(chunk.iter().fold(0.0f32, |peak, s| peak.max(s.abs())));
+                })
+                .unwrap();
+
+            let tone: Vec<u8> = (0..44100)
+                .flat_map(|i| (0.5 * (i as f32 * 0.06).sin()).to_le_bytes())
+                .collect();
+            let mut pacat = Command::new("pacat")
+                .args([
+                    "--device=frosk_test",
+                    "--format=float32le",
+                    "--channels=1",
+                    "--rate=44100",
+                ])
+                .stdin(Stdio::piped())
+                .spawn()
+                .unwrap();
+            std::io::Write::write_all(pacat.stdin.as_mut().unwrap(), &tone).unwrap();
+            drop(pacat.stdin.take());
+            pacat.wait().unwrap();
+
+            let peak = rx.iter().take(100).fold(0.0f32, f32::max);
+            Command::new("pactl")
+                .args(["unload-module", module.trim()])
+                .status()
+                .unwrap();
+            assert!(peak > 0.1, "recorded peak {peak}");
+        }
+    }
+}
+
+pub mod device {
+    use anyhow::Result;
+    use cpal::traits::{DeviceTrait, HostTrait};
+    use regex::Regex;
+    use serde::{Deserialize, Serialize};
+    use std::fmt;
+    use thiserror::Error;
+
+    /// Picks an input device, written in the config as a name, an index or `{ regex = "..." }`.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(untagged)]
+    pub enum DeviceSelector {
+        /// Position in the list of input devices
+        Index(usize),
+        /// Exact device name
+        Name(String),
+        /// Pattern matching part of the device name
+        Regex { regex: String },
+    }
+
+    impl fmt::Display for DeviceSelector {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                DeviceSelector::Index(index) => write!(f, "device #{index}"),
+                DeviceSelector::Name(name) => write!(f, "device {name:?}"),
+                DeviceSelector::Regex { regex } => write!(f, "regex /{regex}/"),
+            }
+        }
+    }
+
+    #[derive(Debug, Error)]
+    pub enum CaptureError {
+        #[error(
+            "no input device matches {selector}, available devices: {}",
+            describe(available)
+        )]
+        DeviceNotFound {
+            selector: DeviceSelector,
+            available: Vec<String>,
+        },
+        #[error("invalid device regex")]
+        InvalidRegex(#[from] regex::Error),
+    }
+
+    fn describe(names: &[String]) -> String {
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        }
+    }
+
+    impl DeviceSelector {
+        /// Returns the index of the first of `names` this selects.
+        pub fn find(&self, names: &[String]) -> Result<usize, CaptureError> {
+            let found = match self {
+                DeviceSelector::Index(index) => (*index < names.len()).then_some(*index),
+                DeviceSelector::Name(name) => names.iter().position(|n| n == name),
+                DeviceSelector::Regex { regex } => {
+                    let regex = Regex::new(regex)?;
+                    names.iter().position(|n| regex.is_match(n))
+                }
+            };
+            found.ok_or_else(|| CaptureError::DeviceNotFound {
+                selector: self.clone(),
+                available: names.to_vec(),
+            })
+        }
+    }
+
+    /// A range of stream configs supported by a device.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SupportedConfig {
+        pub channels: u16,
+        pub min_sample_rate: u32,
+        pub max_sample_rate: u32,
+        pub sample_format: String,
+    }
+
+    impl fmt::Display for SupportedConfig {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{} ch, ", self.channels)?;
+            if self.min_sample_rate == self.max_sample_rate {
+                write!(f, "{} Hz", self.min_sample_rate)?;
+            } else {
+                write!(f, "{}-{} Hz", self.min_sample_rate, self.max_sample_rate)?;
+            }
+            write!(f, ", {}", self.sample_format)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct DeviceInfo {
+        pub index: usize,
+        pub name: String,
+        /// Whether this is the host's default input device
+        pub is_default: bool,
+        pub configs: Vec<SupportedConfig>,
+    }
+
+    /// Lists the input devices of the default host, in the order indices refer to.
+    pub fn input_devices() -> Result<Vec<DeviceInfo>> {
+        let host = cpal::default_host();
+        let default_name = host.default_input_device().and_then(|d| d.name().ok());
+        let mut devices = Vec::new();
+        for (index, device) in host.input_devices()?.enumerate() {
+            let name = device.name().unwrap_or_default();
+            let configs = device
+                .supported_input_configs()
+                .map(|configs| {
+                    configs
+                        .map(|config| SupportedConfig {
+                            channels: config.channels(),
+                            min_sample_rate: config.min_sample_rate().0,
+                            max_sample_rate: config.max_sample_rate().0,
+                            sample_format: config.sample_format().to_string(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            devices.push(DeviceInfo {
+                index,
+                is_default: default_name.as_ref() == Some(&name),
+                name,
+                configs,
+            });
+        }
+        Ok(devices)
+    }
+
+    /// Finds the input device picked by `selector`, failing with [`CaptureError`] if there is none.
+    pub fn find_input_device(selector: &DeviceSelector) -> Result<cpal::Device> {
+        let host = cpal::default_host();
+        let devices: Vec<cpal::Device> = host.input_devices()?.collect();
+        let names: Vec<String> = devices
+            .iter()
+            .map(|device| device.name().unwrap_or_default())
+            .collect();
+        let index = selector.find(&names)?;
+        Ok(devices.into_iter().nth(index).unwrap())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_selector_finds_device() {
+            let names =
+                ["MacBook Pro Microphone", "BlackHole 2ch", "BlackHole 16ch"].map(String::from);
+
+            let by_name = DeviceSelector::Name("BlackHole 16ch".to_string());
+            assert_eq!(by_name.find(&names).unwrap(), 2);
+            assert_eq!(DeviceSelector::Index(0).find(&names).unwrap(), 0);
+            let by_regex = DeviceSelector::Regex {
+                regex: "^BlackHole".to_string(),
+            };
+            assert_eq!(by_regex.find(&names).unwrap(), 1);
+
+            let missing = DeviceSelector::Name("BlackHole".to_string());
+            assert!(matches!(
+                missing.find(&names),
+                Err(CaptureError::DeviceNotFound { available, .. }) if available.len() == 3
+            ));
+            assert!(DeviceSelector::Index(3).find(&names).is_err());
+            let invalid = DeviceSelector::Regex {
+                regex: "(".to_string(),
+            };
+            assert!(matches!(
+                invalid.find(&names),
+                Err(CaptureError::InvalidRegex(_))
+            ));
+        }
+    }
+}
+
+pub mod file {
+    use super::{resample::Resampler, AudioCapture, CaptureFlags, CaptureHandle, ThreadControl};
+    use anyhow::{bail, Result};
+    use std::{
+        fs::File,
+        io::{BufReader, Read},
+        path::Path,
+        sync::{mpsc, Arc},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    /// Chunk size used by default, matching the buffer size of the cpal capturer.
+    pub const DEFAULT_CHUNK_SIZE: usize = 440;
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub enum ReplaySpeed {
+        /// Chunks are delivered at the pace they would arrive from a live device.
+        #[default]
+        RealTime,
+        /// Chunks are delivered back-to-back without sleeping.
+        AsFastAsPossible,
+    }
+
+    /// Replays a recorded WAV or FLAC file through the capture callback, so the
+    /// rest of the pipeline can be exercised without a loopback device.
+    /// Multi-channel recordings are downmixed to mono.
+    #[derive(Debug, Clone)]
+    pub struct FileCapturer {
+        samples: Arc<[f32]>,
+        sample_rate: u32,
+        chunk_size: usize,
+        speed: ReplaySpeed,
+    }
+
+    impl FileCapturer {
+        pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+            let path = path.as_ref();
+            let extension = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(|e| e.to_ascii_lowercase());
+            let (samples, sample_rate) = match extension.as_deref() {
+                Some("wav") => decode_wav(BufReader::new(File::open(path)?))?,
+                Some("flac") => decode_flac(BufReader::new(File::open(path)?))?,
+                _ => bail!("unsupported audio file: {}", path.display()),
+            };
+            Ok(Self::from_samples(samples, sample_rate))
+        }
+
+        pub fn from_wav_bytes(bytes: &[u8]) -> Result<Self> {
+            let (samples, sample_rate) = decode_wav(bytes)?;
+            Ok(Self::from_samples(samples, sample_rate))
+        }
+
+        pub fn from_samples(samples: Vec<f32>, sample_rate: u32) -> Self {
+            Self {
+                samples: samples.into(),
+                sample_rate,
+                chunk_size: DEFAULT_CHUNK_SIZE,
+                speed: ReplaySpeed::default(),
+            }
+        }
+
+        pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+            assert!(chunk_size > 0, "chunk size must be positive");
+            self.chunk_size = chunk_size;
+            self
+        }
+
+        pub fn with_speed(mut self, speed: ReplaySpeed) -> Self {
+            self.speed = speed;
+            self
+        }
+
+        /// The same recording at `sample_rate`, so it can be replayed against
+        /// templates sampled at that rate, as live capture is converted to it.
+        pub fn resampled(&self, sample_rate: u32) -> Self {
+            if sample_rate == self.sample_rate {
+                return self.clone();
+            }
+            let mut samples = Vec::new();
+            Resampler::new(self.sample_rate, sample_rate).process(&self.samples, &mut samples);
+            Self {
+                samples: samples.into(),
+                sample_rate,
+                ..self.clone()
+            }
+        }
+
+        pub fn sample_rate(&self) -> u32 {
+            self.sample_rate
+        }
+
+        pub fn samples(&self) -> &[f32] {
+            &self.samples
+        }
+
+        /// Feeds the whole file through `callback` on the current thread,
+        /// returning once the last chunk has been delivered.
+        pub fn replay(&self, callback: impl FnMut(&[f32], Instant)) {
+            replay(
+                &self.samples,
+                self.sample_rate,
+                self.chunk_size,
+                self.speed,
+                &CaptureFlags::default(),
+                callback,
+            );
+        }
+    }
+
+    impl AudioCapture for FileCapturer {
+        /// Replays on a background thread. Pausing holds the position in the file.
+        fn capture_game_audio(
+            &self,
+            callback: impl FnMut(&[f32], Instant) + Send + 'static,
+        ) -> Result<CaptureHandle> {
+            let samples = Arc::clone(&self.samples);
+            let (sample_rate, chunk_size, speed) = (self.sample_rate, self.chunk_size, self.speed);
+            let flags = Arc::new(CaptureFlags::default());
+            let thread_flags = Arc::clone(&flags);
+            let thread = thread::Builder::new()
+                .name("frosk-file-replay".into())
+                .spawn(move || {
+                    replay(
+                        &samples,
+                        sample_rate,
+                        chunk_size,
+                        speed,
+                        &thread_flags,
+                        callback,
+                    )
+                })?;
+            // replaying a decoded file cannot fail, so no errors are ever sent
+            let (_, errors) = mpsc::channel();
+            Ok(CaptureHandle::new(
+                ThreadControl::new(flags, thread),
+                errors,
+            ))
+        }
+    }
+
+    fn replay(
+        samples: &[f32],
+        sample_rate: u32,
+        chunk_size: usize,
+        speed: ReplaySpeed,
+        flags: &CaptureFlags,
+        mut callback: impl FnMut(&[f32], Instant),
+    ) {
+        let mut start = Instant::now();
+        let mut delivered = 0;
+        for chunk in samples.chunks(chunk_size) {
+            if flags.is_paused() {
+                let paused_at = Instant::now();
+                while flags.is_paused() && !flags.is_stopped() {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                // the pause does not count towards the recording's timeline
+                start += paused_at.elapsed();
+            }
+            if flags.is_stopped() {
+                return;
+            }
+            delivered += chunk.len();
+            let captured_at = match speed {
+                ReplaySpeed::RealTime => {
+                    // a live device hands over a chunk once all of its samples have been recorded
+                    let due =
+                        start + Duration::from_secs_f64(delivered as f64 / sample_rate as f64);
+                    if let Some(remaining) = due.checked_duration_since(Instant::now()) {
+                        thread::sleep(remaining);
+                    }
+                    due
+                }
+                ReplaySpeed::AsFastAsPossible => Instant::now(),
+            };
+            callback(chunk, captured_at);
+        }
+    }
+
+    /// Decodes a WAV stream into mono samples in [-1, 1], returning them along
+    /// with the sample rate.
+    pub fn decode_wav(reader: impl Read) -> Result<(Vec<f32>, u32)> {
+        let reader = hound::WavReader::new(reader)?;
+        let spec = reader.spec();
+        let interleaved = match spec.sample_format {
+            hound::SampleFormat::Float => reader.into_samples::<f32>().collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .into_samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 / scale))
+                    .collect::<Result<_, _>>()?
+            }
+        };
+        Ok((downmix(interleaved, spec.channels), spec.sample_rate))
+    }
+
+    /// Decodes a FLAC stream into mono samples in [-1, 1], returning them along
+    /// with the sample rate.
+    pub fn decode_flac(reader: impl Read) -> Result<(Vec<f32>, u32)> {
+        let mut reader = claxon::FlacReader::new(reader)?;
+        let info = reader.streaminfo();
+        let scale = (1i64 << (info.bits_per_sample - 1)) as f32;
+        let interleaved = reader
+            .samples()
+            .map(|s| s.map(|s| s as f32 / scale))
+            .collect::<Result<_, _>>()?;
+        Ok((downmix(interleaved, info.channels as u16), info.sample_rate))
+    }
+
+    fn downmix(interleaved: Vec<f32>, channels: u16) -> Vec<f32> {
+        if channels <= 1 {
+            return interleaved;
+        }
+        interleaved
+            .chunks_exact(channels as usize)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+            .collect()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::{io::Cursor, sync::mpsc};
+
+        fn stereo_wav(frames: &[(i16, i16)]) -> Vec<u8> {
+            let spec = hound::WavSpec {
+                channels: 2,
+                sample_rate: 8000,
+                bits_per_sample: 16,
+                sample_format: hound::SampleFormat::Int,
+            };
+            let mut bytes = Cursor::new(Vec::new());
+            let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+            for (left, right) in frames {
+                writer.write_sample(*left).unwrap();
+                writer.write_sample(*right).unwrap();
+            }
+            writer.finalize().unwrap();
+            bytes.into_inner()
+        }
+
+        #[test]
+        fn test_decode_wav_downmixes_and_scales() {
+            let bytes = stereo_wav(&[(16384, 16384), (-16384, 0), (i16::MIN, i16::MIN)]);
+            let (samples, sample_rate) = decode_wav(bytes.as_slice()).unwrap();
+            assert_eq!(sample_rate, 8000);
+            assert_eq!(samples, vec![0.5, -0.25, -1.0]);
+        }
+
+        #[test]
+        fn test_resampled_recording_keeps_its_duration() {
+            let capturer = FileCapturer::from_samples(vec![0.25; 4800], 48000).with_chunk_size(64);
+            let resampled = capturer.resampled(44100);
+            assert_eq!(resampled.sample_rate(), 44100);
+            assert!((resampled.samples().len() as i64 - 4410).abs() <= 1);
+            assert!(resampled.samples().iter().all(|&x| x == 0.25));
+            assert_eq!(resampled.chunk_size, 64);
+            assert_eq!(capturer.resampled(48000).samples(), capturer.samples());
+        }
+
+        #[test]
+        fn test_capture_delivers_every_sample_in_chunks() {
+            let source: Vec<f32> = (0..1000).map(|i| i as f32).collect();
+            let capturer = FileCapturer::from_samples(source.clone(), 8000)
+                .with_chunk_size(64)
+                .with_speed(ReplaySpeed::AsFastAsPossible);
+
+            let (tx, rx) = mpsc::channel();
+            let handle = capturer
+                .capture_game_audio(move |chunk, _| tx.send(chunk.to_vec()).unwrap())
+                .unwrap();
+
+            // the channel disconnects once the replay thread drops the callback
+            let chunks: Vec<Vec<f32>> = rx.iter().collect();
+            assert_eq!(chunks.len(), 16);
+            assert!(chunks[..15].iter().all(|c| c.len() == 64));
+            assert_eq!(chunks.concat(), source);
+            // the callback is dropped just before the thread exits
+            let deadline = Instant::now() + Duration::from_secs(1);
+            while !handle.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert!(handle.is_finished());
+            assert_eq!(handle.errors().count(), 0);
+        }
+
+        #[test]
+        fn test_handle_pauses_and_stops_replay() {
+            // a chunk every 10ms for 100s, so the replay only ends when stopped
+            let capturer = FileCapturer::from_samples(vec![0.0; 800_000], 8000).with_chunk_size(80);
+            let (tx, rx) = mpsc::channel();
+            let mut handle = capturer
+                .capture_game_audio(move |chunk, _| tx.send(chunk.len()).unwrap())
+                .unwrap();
+            rx.recv_timeout(Duration::from_secs(1)).unwrap();
+
+            handle.pause().unwrap();
+            assert!(handle.is_paused());
+            // a chunk may already have been on its way when pausing
+            thread::sleep(Duration::from_millis(50));
+            rx.try_iter().count();
+            thread::sleep(Duration::from_millis(50));
+            assert_eq!(rx.try_iter().count(), 0);
+
+            handle.resume().unwrap();
+            rx.recv_timeout(Duration::from_secs(1)).unwrap();
+            assert!(!handle.is_finished());
+
+            handle.stop();
+            // stopping joins the replay thread, which drops the callback and disconnects
+            rx.iter().for_each(drop);
         }
     }
 }
@@ -0,0 +1,485 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use super::{
+    capture::{device::DeviceSelector, CaptureBackend, CaptureConfig},
+    dsp::{
+        CorrelationMethod, DetectorConfig, Normalization, SignalProcessor, Target, Template,
+        TemplateKind, DEFAULT_HOP,
+    },
+    fishing::{FishingConfig, KeyBindings},
+    recorder::RecorderConfig,
+};
+
+/// Everything that can be set in the config file. Missing fields take their
+/// default value, so an empty file is a valid config.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub capture: CaptureConfig,
+    pub detection: DetectionConfig,
+    pub keys: KeyBindings,
+    pub fishing: FishingConfig,
+    pub gui: GuiConfig,
+    pub recording: RecorderConfig,
+    /// Sounds to detect, the bundled fish bite sound if empty
+    pub templates: Vec<TemplateConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DetectionConfig {
+    pub method: CorrelationMethod,
+    pub normalization: Normalization,
+    /// Samples between consecutive correlation values
+    pub hop: usize,
+    #[serde(flatten)]
+    pub detector: DetectorConfig,
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        Self {
+            method: CorrelationMethod::default(),
+            normalization: Normalization::default(),
+            hop: DEFAULT_HOP,
+            detector: DetectorConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuiConfig {
+    /// Correlation values kept in the plot
+    pub retention: usize,
+}
+
+impl Default for GuiConfig {
+    fn default() -> Self {
+        Self { retention: 8000 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TemplateConfig {
+    pub kind: TemplateKind,
+    /// WAV file of the sound, relative to the config file
+    pub path: PathBuf,
+    /// Name shown for the template, the file name by default
+    pub name: Option<String>,
+}
+
+impl TemplateConfig {
+    pub fn load(&self) -> Result<Template> {
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => self
+                .path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let target = Target::from_wav_file(&self.path)
+            .with_context(|| format!("loading template {}", self.path.display()))?;
+        Ok(Template::new(name, self.kind, target))
+    }
+}
+
+impl FromStr for TemplateConfig {
+    type Err = anyhow::Error;
+
+    /// Parses a template given on the command line as `KIND=WAV_FILE`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, path) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected KIND=WAV_FILE, got {s}"))?;
+        Ok(Self {
+            kind: kind.parse()?,
+            path: PathBuf::from(path),
+            name: None,
+        })
+    }
+}
+
+impl Config {
+    /// `config.toml` in the `frosk` folder of the platform's config directory.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("frosk").join("config.toml"))
+    }
+
+    /// Loads the config at `path`, falling back to the defaults if there is no such file.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents, path.parent().unwrap_or(Path::new("")))
+                .with_context(|| format!("reading config {}", path.display())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("reading config {}", path.display())),
+        }
+    }
+
+    /// Parses a config, resolving template paths relative to `dir`.
+    pub fn parse(contents: &str, dir: &Path) -> Result<Self> {
+        let mut config: Self = toml::from_str(contents)?;
+        if config.detection.hop == 0 {
+            bail!("detection.hop must be positive");
+        }
+        for template in &mut config.templates {
+            template.path = dir.join(&template.path);
+        }
+        config.recording.dir = dir.join(&config.recording.dir);
+        Ok(config)
+    }
+
+    /// The fishing timings, confirming casts whenever there is a template to
+    /// confirm them with unless `confirm_casts` is set.
+    pub fn fishing_config(&self) -> FishingConfig {
+        let confirmable = self.templates.iter().any(|template| {
+            matches!(
+                template.kind,
+                TemplateKind::CastSplash | TemplateKind::BobberLanded
+            )
+        });
+        FishingConfig {
+            confirm_casts: Some(self.fishing.confirm_casts.unwrap_or(confirmable)),
+            ..self.fishing
+        }
+    }
+
+    /// Applies the detection options shared by the command line tools: templates
+    /// given with `--template` replace the configured ones, and `--fft` and
+    /// `--normalized` replace the configured method and normalization.
+    pub fn override_detection(
+        &mut self,
+        templates: &[TemplateConfig],
+        fft: bool,
+        normalized: bool,
+    ) {
+        if !templates.is_empty() {
+            self.templates = templates.to_vec();
+        }
+        if fft {
+            self.detection.method = CorrelationMethod::Fft;
+        }
+        if normalized {
+            self.detection.normalization = Normalization::TargetAndWindow;
+        }
+    }
+
+    /// Builds a processor for the configured templates and detection settings.
+    pub fn signal_processor(&self) -> Result<SignalProcessor> {
+        let mut signal_processor = SignalProcessor::from_templates([], self.detection.method);
+        for template in &self.templates {
+            signal_processor.add_template(template.load()?)?;
+        }
+        if self.templates.is_empty() {
+            signal_processor.add_template(Template::default())?;
+        }
+        Ok(signal_processor
+            .with_hop(self.detection.hop)
+            .with_normalization(self.detection.normalization)
+            .with_detector_config(self.detection.detector))
+    }
+}
+
+/// Stores `device` in the config file at `path`, switching to the device
+/// backend, and keeps the other settings as they are written but drops any comments.
+pub fn save_device(path: &Path, device: &DeviceSelector) -> Result<()> {
+    update_file(path, |table| {
+        let capture = table
+            .entry("capture")
+            .or_insert_with(|| toml::Table::new().into())
+            .as_table_mut()
+            .context("capture must be a table")?;
+        capture.insert("device".to_string(), toml::Value::try_from(device)?);
+        capture.insert(
+            "backend".to_string(),
+            toml::Value::try_from(CaptureBackend::Device)?,
+        );
+        Ok(())
+    })
+}
+
+/// Stores `keys` in the config file at `path` like [`save_device`].
+pub fn save_keys(path: &Path, keys: &KeyBindings) -> Result<()> {
+    update_file(path, |table| {
+        table.insert("keys".to_string(), toml::Value::try_from(keys)?);
+        Ok(())
+    })
+}
+
+/// Rewrites the config file at `path` with `update`, creating it if needed.
+fn update_file(path: &Path, update: impl FnOnce(&mut toml::Table) -> Result<()>) -> Result<()> {
+    let mut table: toml::Table = match fs::read_to_string(path) {
+        Ok(contents) => contents.parse()?,
+        Err(err) if err.kind() == ErrorKind::NotFound => toml::Table::new(),
+        Err(err) => return Err(err.into()),
+    };
+    update(&mut table)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, toml::to_string(&table)?)?;
+    Ok(())
+}
+
+/// Watches a config file by polling its modification time.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    /// Starts watching `path`, treating its current contents as already loaded.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let modified = modified(&path);
+        Self { path, modified }
+    }
+
+    /// Returns the reloaded config if the file changed since the last poll.
+    pub fn poll(&mut self) -> Option<Result<Config>> {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        Some(Config::load(&self.path))
+    }
+
+    /// Polls on a background thread every `interval`, calling `on_change` with each
+    /// reloaded config. Invalid configs are reported on stderr and otherwise ignored.
+    pub fn spawn(
+        mut self,
+        interval: Duration,
+        mut on_change: impl FnMut(Config) + Send + 'static,
+    ) -> thread::JoinHandle<()> {
+        thread::Builder::new()
+            .name("frosk-config-watcher".to_string())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                match self.poll() {
+                    Some(Ok(config)) => on_change(config),
+                    Some(Err(err)) => eprintln!("ignoring invalid config: {err:#}"),
+                    None => {}
+                }
+            })
+            .expect("failed to spawn config watcher thread")
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        capture::pulse::PulseSource, dsp::ThresholdMode, input::KeyBinding, process::ProcessQuery,
+    };
+
+    #[test]
+    fn test_partial_config_keeps_defaults() {
+        let config = Config::parse(
+            r#"
+            [capture]
+            device = "Loopback"
+
+            [detection]
+            method = "fft"
+            threshold = 0.4
+
+            [detection.threshold_mode]
+            mode = "adaptive"
+            sigmas = 5.0
+
+            [keys]
+            cast = "Shift+F10"
+
+            [[templates]]
+            kind = "cast-splash"
+            path = "sounds/splash.wav"
+            "#,
+            Path::new("/etc/frosk"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.capture.device,
+            DeviceSelector::Name("Loopback".to_string())
+        );
+        assert_eq!(
+            config.capture.chunk_size,
+            CaptureConfig::default().chunk_size
+        );
+        assert_eq!(config.detection.method, CorrelationMethod::Fft);
+        assert_eq!(config.detection.hop, DEFAULT_HOP);
+        assert_eq!(config.detection.detector.threshold, 0.4);
+        let ThresholdMode::Adaptive(adaptive) = config.detection.detector.threshold_mode else {
+            panic!("expected an adaptive threshold");
+        };
+        assert_eq!(adaptive.sigmas, 5.0);
+        assert_eq!(adaptive.window_ms, 5000);
+        assert_eq!(config.keys.cast, "Shift+F10".parse::<KeyBinding>().unwrap());
+        assert_eq!(config.keys.reel, KeyBindings::default().reel);
+        assert_eq!(config.fishing, FishingConfig::default());
+        assert_eq!(
+            config.templates[0].path,
+            Path::new("/etc/frosk/sounds/splash.wav")
+        );
+
+        let pulse = Config::parse(
+            "[capture]\npulse = { application = \"Wow.exe\" }",
+            Path::new(""),
+        )
+        .unwrap();
+        assert_eq!(
+            pulse.capture.pulse,
+            PulseSource::Application("Wow.exe".to_string())
+        );
+        let process = Config::parse(
+            "[capture]\nprocess = { executable = \"Wow.exe\" }",
+            Path::new(""),
+        )
+        .unwrap();
+        assert_eq!(
+            process.capture.process,
+            ProcessQuery::Executable("Wow.exe".to_string())
+        );
+        let by_index = Config::parse("[capture]\ndevice = 2", Path::new("")).unwrap();
+        assert_eq!(by_index.capture.device, DeviceSelector::Index(2));
+        let by_regex =
+            Config::parse("[capture]\ndevice = { regex = \"^Black\" }", Path::new("")).unwrap();
+        assert_eq!(
+            by_regex.capture.device,
+            DeviceSelector::Regex {
+                regex: "^Black".to_string()
+            }
+        );
+
+        assert!(Config::parse("[keys]\ncast = \"Hyper+F1\"", Path::new("")).is_err());
+        assert!(Config::parse("[detection]\nhop = 0", Path::new("")).is_err());
+    }
+
+    #[test]
+    fn test_default_config_round_trips() {
+        let contents = toml::to_string(&Config::default()).unwrap();
+        assert_eq!(
+            Config::parse(&contents, Path::new("")).unwrap(),
+            Config::default()
+        );
+    }
+
+    #[test]
+    fn test_command_line_templates_replace_configured_ones() {
+        let template: TemplateConfig = "cast-splash=splash.wav".parse().unwrap();
+        assert_eq!(template.kind, TemplateKind::CastSplash);
+        assert_eq!(template.path, Path::new("splash.wav"));
+        assert!("splash.wav".parse::<TemplateConfig>().is_err());
+
+        let mut config = Config::parse(
+            "[[templates]]\nkind = \"fish-bite\"\npath = \"bite.wav\"\n",
+            Path::new(""),
+        )
+        .unwrap();
+        config.override_detection(&[], true, false);
+        assert_eq!(config.templates[0].kind, TemplateKind::FishBite);
+        assert_eq!(config.detection.method, CorrelationMethod::Fft);
+        assert_eq!(config.detection.normalization, Normalization::default());
+        config.override_detection(std::slice::from_ref(&template), false, true);
+        assert_eq!(config.templates, vec![template]);
+        assert_eq!(
+            config.detection.normalization,
+            Normalization::TargetAndWindow
+        );
+    }
+
+    #[test]
+    fn test_casts_are_confirmed_with_a_template_unless_disabled() {
+        let splash = "[[templates]]\nkind = \"cast-splash\"\npath = \"splash.wav\"\n";
+        let confirm_casts = |contents: &str| {
+            Config::parse(contents, Path::new(""))
+                .unwrap()
+                .fishing_config()
+                .confirm_casts
+        };
+        assert_eq!(confirm_casts(""), Some(false));
+        assert_eq!(confirm_casts(splash), Some(true));
+        assert_eq!(
+            confirm_casts(&format!("[fishing]\nconfirm_casts = false\n\n{splash}")),
+            Some(false)
+        );
+        assert_eq!(
+            confirm_casts("[fishing]\nconfirm_casts = true\n"),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_save_device_keeps_other_settings() {
+        let dir = std::env::temp_dir().join(format!("frosk-save-{}", std::process::id()));
+        let path = dir.join("config.toml");
+        save_device(&path, &DeviceSelector::Index(1)).unwrap();
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.capture.device, DeviceSelector::Index(1));
+        assert_eq!(config.capture.backend, CaptureBackend::Device);
+
+        fs::write(
+            &path,
+            "[capture]\nchunk_size = 512\n\n[[templates]]\nkind = \"fish-bite\"\npath = \"bite.wav\"\n",
+        )
+        .unwrap();
+        save_device(&path, &DeviceSelector::Name("Loopback".to_string())).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        let config = Config::parse(&contents, Path::new("")).unwrap();
+        assert_eq!(
+            config.capture.device,
+            DeviceSelector::Name("Loopback".to_string())
+        );
+        assert_eq!(config.capture.chunk_size, 512);
+        assert_eq!(config.templates[0].path, Path::new("bite.wav"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_keys_keeps_the_device() {
+        let dir = std::env::temp_dir().join(format!("frosk-keys-{}", std::process::id()));
+        let path = dir.join("config.toml");
+        save_device(&path, &DeviceSelector::Index(1)).unwrap();
+        let keys = KeyBindings {
+            reel: "Shift+F9".parse().unwrap(),
+            cast: "F10".parse().unwrap(),
+        };
+        save_keys(&path, &keys).unwrap();
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.keys, keys);
+        assert_eq!(config.capture.device, DeviceSelector::Index(1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_watcher_reloads_changed_file() {
+        let path = std::env::temp_dir().join(format!("frosk-config-{}.toml", std::process::id()));
+        let mut watcher = ConfigWatcher::new(&path);
+        assert!(watcher.poll().is_none());
+
+        fs::write(&path, "[gui]\nretention = 100").unwrap();
+        let config = watcher.poll().unwrap().unwrap();
+        assert_eq!(config.gui.retention, 100);
+        assert!(watcher.poll().is_none());
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(watcher.poll().unwrap().unwrap(), Config::default());
+    }
+}
@@ -1,117 +1,857 @@
-use ringbuf::{storage::Owning, traits::*, wrap::caching::Caching, SharedRb, StaticRb};
-use std::{mem::MaybeUninit, sync::Arc};
+use anyhow::{anyhow, bail, Result};
+use ringbuf::{traits::*, HeapCons, HeapProd, HeapRb};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::File,
+    io::BufReader,
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
-use super::event::FroskEvent;
+use super::{
+    capture::{file::decode_wav, resample::Resampler, SAMPLE_RATE},
+    event::{Detection, FroskEvent},
+};
 
 const TARGET_BYTES: &[u8] = include_bytes!("../../sounds/FishBite.wav");
 
-const fn target_sample_count() -> u32 {
-    let data = TARGET_BYTES;
-    let num_channels = u16::from_le_bytes([data[22], data[23]]);
-    let bits_per_sample = u16::from_le_bytes([data[34], data[35]]);
-    let mut offset = 12;
-    while offset + 8 < data.len() {
-        let chunk_id = [
-            data[offset],
-            data[offset + 1],
-            data[offset + 2],
-            data[offset + 3],
-        ];
-        let chunk_size = u32::from_le_bytes([
-            data[offset + 4],
-            data[offset + 5],
-            data[offset + 6],
-            data[offset + 7],
-        ]);
-        if let [b'd', b'a', b't', b'a'] = chunk_id {
-            let data_chunk_size = chunk_size;
-            let bytes_per_sample = (bits_per_sample / 8) as u32;
-            return data_chunk_size / (num_channels as u32 * bytes_per_sample);
-        }
+/// Number of samples between consecutive correlation values.
+pub const DEFAULT_HOP: usize = 10;
+
+/// Number of samples per block of the FFT correlator. Correlations are only
+/// available once a full block has been pushed, so this bounds the added latency.
+const FFT_BLOCK_SIZE: usize = 2048;
 
-        offset += 8 + chunk_size as usize; // Move to next chunk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CorrelationMethod {
+    /// Dot product of the whole buffer with the target at every hop.
+    #[default]
+    Naive,
+    /// Partitioned overlap-save convolution with the time-reversed target.
+    /// Produces the same correlation stream as [`CorrelationMethod::Naive`],
+    /// but in bursts of a block at a time.
+    Fft,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Normalization {
+    /// Divide by the energy of the target only, so scores follow the in-game volume.
+    #[default]
+    Target,
+    /// Divide by the energies of both the target and the buffered window, giving
+    /// scores in [-1, 1] that are independent of the volume.
+    TargetAndWindow,
+}
+
+/// Decides when a correlation stream counts as a match.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DetectorConfig {
+    /// Correlation a template has to exceed to be reported.
+    pub threshold: f32,
+    /// Correlation the score has to fall below again before the template can
+    /// be reported another time, so a single noisy peak isn't reported twice.
+    pub rearm_level: f32,
+    /// Minimum time between two reports of the same template.
+    pub refractory_ms: u32,
+    /// Time a score has to stop rising after crossing the threshold before
+    /// it is reported, so the event carries the peak of the match.
+    pub peak_lookahead_ms: u32,
+    pub threshold_mode: ThresholdMode,
+}
+
+impl Default for DetectorConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.3,
+            rearm_level: 0.15,
+            refractory_ms: 1000,
+            peak_lookahead_ms: 100,
+            threshold_mode: ThresholdMode::default(),
+        }
     }
+}
 
-    0
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "kebab-case")]
+pub enum ThresholdMode {
+    /// Use `threshold` and `rearm_level` as they are.
+    #[default]
+    Fixed,
+    /// Derive the levels from the running statistics of the correlation
+    /// stream instead, ignoring `threshold` and `rearm_level`.
+    Adaptive(AdaptiveThreshold),
 }
 
-const TARGET_SAMPLE_COUNT: usize = target_sample_count() as usize;
+/// Reports a template when its score stands out from the background of its
+/// correlation stream, which depends on music, voice chat, the zone, etc.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdaptiveThreshold {
+    /// Standard deviations above the background mean a score has to exceed.
+    pub sigmas: f32,
+    /// Standard deviations above the background mean the score has to fall below to re-arm.
+    pub rearm_sigmas: f32,
+    /// Time constant of the running mean and variance. Nothing is reported
+    /// until this much audio has been seen.
+    pub window_ms: u32,
+    /// Scores below this are never reported, however quiet the background.
+    pub min_threshold: f32,
+}
 
-#[derive(Default)]
+impl Default for AdaptiveThreshold {
+    fn default() -> Self {
+        Self {
+            sigmas: 6.0,
+            rearm_sigmas: 3.0,
+            window_ms: 5000,
+            min_threshold: 0.05,
+        }
+    }
+}
+
+/// Correlates the captured audio with a set of templates, turning matches into
+/// the [`FroskEvent`] of each template's kind.
 pub struct SignalProcessor {
-    buffer: Buffer,
-    target: Target,
-    time_since_last_bite: u32,
+    detectors: Vec<Detector>,
+    method: CorrelationMethod,
+    normalization: Normalization,
+    detector_config: DetectorConfig,
+    hop: usize,
+    clock: Clock,
+}
+
+impl Default for SignalProcessor {
+    fn default() -> Self {
+        Self::new(CorrelationMethod::default())
+    }
 }
 
 impl SignalProcessor {
-    pub fn process_chunk(&mut self, chunk: &[f32]) {
-        self.buffer.process_chunk(chunk);
+    /// Creates a processor listening for the bundled fish bite sound.
+    pub fn new(method: CorrelationMethod) -> Self {
+        Self::from_templates([Template::default()], method)
     }
 
-    pub fn determine_event(&mut self, correlation: f32) -> Option<FroskEvent> {
-        if correlation > 0.3 && self.time_since_last_bite > 50 {
-            self.time_since_last_bite = 0;
-            return Some(FroskEvent::FishBite { score: correlation });
+    pub fn from_templates(
+        templates: impl IntoIterator<Item = Template>,
+        method: CorrelationMethod,
+    ) -> Self {
+        let mut signal_processor = Self {
+            detectors: Vec::new(),
+            method,
+            normalization: Normalization::default(),
+            detector_config: DetectorConfig::default(),
+            hop: DEFAULT_HOP,
+            clock: Clock::default(),
+        };
+        for template in templates {
+            signal_processor.push_template(template);
+        }
+        signal_processor
+    }
+
+    /// Starts listening for `template`, resampled to the rate of the first
+    /// template. Fails once audio has been processed, as the new detector
+    /// would be out of step with the others.
+    pub fn add_template(&mut self, template: Template) -> Result<()> {
+        if self.clock.latest.is_some() {
+            bail!(
+                "template {} must be added before any audio is processed",
+                template.name
+            );
+        }
+        self.push_template(template);
+        Ok(())
+    }
+
+    /// The audio is only captured at one rate, so every template has to share it.
+    fn push_template(&mut self, mut template: Template) {
+        let sample_rate = self.sample_rate();
+        if !self.detectors.is_empty() && template.target.sample_rate() != sample_rate {
+            template.target = template.target.resampled(sample_rate);
+        }
+        self.detectors.push(Detector::new(template, self.method));
+    }
+
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    pub fn with_detector_config(mut self, detector_config: DetectorConfig) -> Self {
+        self.detector_config = detector_config;
+        self
+    }
+
+    /// Changes how matches are detected, taking effect from the next correlation on.
+    pub fn set_detector_config(&mut self, detector_config: DetectorConfig) {
+        self.detector_config = detector_config;
+    }
+
+    pub fn detector_config(&self) -> DetectorConfig {
+        self.detector_config
+    }
+
+    pub fn with_hop(mut self, hop: usize) -> Self {
+        assert!(hop > 0, "hop must be positive");
+        self.hop = hop;
+        self
+    }
+
+    pub fn hop(&self) -> usize {
+        self.hop
+    }
+
+    /// Forgets all audio processed so far, leaving the processor as it was
+    /// created without planning its FFTs again, e.g. to replay another recording.
+    pub fn reset(&mut self) {
+        for detector in &mut self.detectors {
+            detector.reset();
+        }
+        self.clock = Clock::default();
+    }
+
+    pub fn templates(&self) -> impl Iterator<Item = &Template> {
+        self.detectors.iter().map(|d| &d.template)
+    }
+
+    /// Sample rate the audio should be captured at, that of the first template
+    /// and the one the others were resampled to.
+    pub fn sample_rate(&self) -> u32 {
+        self.templates()
+            .next()
+            .map_or(SAMPLE_RATE, |template| template.target.sample_rate())
+    }
+
+    /// Pushes `chunk`, whose last sample was captured at `captured_at`, into
+    /// the processor and returns, for every `hop` samples that became available,
+    /// the correlation with each template in the order they were added.
+    pub fn correlate(&mut self, chunk: &[f32], captured_at: Instant) -> Vec<Vec<f32>> {
+        self.clock.advance(chunk.len() as u64, captured_at);
+        let (hop, normalization) = (self.hop, self.normalization);
+        let template_count = self.detectors.len();
+        let mut correlations: Vec<Vec<f32>> = Vec::new();
+        for (i, detector) in self.detectors.iter_mut().enumerate() {
+            // every detector sees the same samples, so they all produce the same number of hops
+            for (j, correlation) in detector.correlate(chunk, hop, normalization).enumerate() {
+                if i == 0 {
+                    correlations.push(Vec::with_capacity(template_count));
+                }
+                correlations[j].push(correlation);
+            }
+        }
+        correlations
+    }
+
+    /// Decides whether `correlation` with the template at index `template`
+    /// is a match, and if so returns the corresponding event.
+    ///
+    /// Once a score crosses the threshold, the event is held back until the
+    /// score hasn't risen for the configured look-ahead, so that it reports
+    /// the peak of the match rather than its rising edge.
+    pub fn determine_event(&mut self, template: usize, correlation: f32) -> Option<FroskEvent> {
+        let config = &self.detector_config;
+        let clock = &self.clock;
+        let hop = self.hop as u64;
+        let detector = &mut self.detectors[template];
+        let sample_rate = detector.template.target.sample_rate() as u64;
+        let previous = std::mem::replace(&mut detector.previous, correlation);
+        detector.samples_processed += hop;
+
+        if let Some(peak) = &mut detector.peak {
+            if correlation > peak.score {
+                *peak = Peak {
+                    score: correlation,
+                    before: previous,
+                    after: None,
+                    end: detector.samples_processed,
+                };
+            } else if peak.after.is_none() {
+                peak.after = Some(correlation);
+            }
+            let lookahead = config.peak_lookahead_ms as u64 * sample_rate / 1000;
+            return detector.report_peak_after(lookahead, hop, clock);
+        }
+
+        let (threshold, rearm_level, warmed_up) = match config.threshold_mode {
+            ThresholdMode::Fixed => (config.threshold, config.rearm_level, true),
+            ThresholdMode::Adaptive(adaptive) => {
+                let background = &detector.background;
+                let deviation = background.variance.sqrt();
+                (
+                    (background.mean + adaptive.sigmas * deviation).max(adaptive.min_threshold),
+                    background.mean + adaptive.rearm_sigmas * deviation,
+                    background.samples >= adaptive.window_ms as u64 * sample_rate / 1000,
+                )
+            }
+        };
+        if correlation < rearm_level {
+            detector.armed = true;
+        }
+
+        let refractory_samples = config.refractory_ms as u64 * sample_rate / 1000;
+        if correlation > threshold
+            && detector.armed
+            && warmed_up
+            && detector
+                .last_peak_end
+                .is_none_or(|end| detector.samples_processed - end > refractory_samples)
+        {
+            detector.armed = false;
+            detector.peak = Some(Peak {
+                score: correlation,
+                before: previous,
+                after: None,
+                end: detector.samples_processed,
+            });
+            let lookahead = config.peak_lookahead_ms as u64 * sample_rate / 1000;
+            return detector.report_peak_after(lookahead, hop, clock);
+        }
+
+        // everything but the peaks counts as background, including scores above
+        // the threshold, so the levels catch up with a rising noise floor
+        if let ThresholdMode::Adaptive(adaptive) = config.threshold_mode {
+            let window = adaptive.window_ms as u64 * sample_rate / 1000;
+            detector.background.push(correlation, hop, window);
         }
-        self.time_since_last_bite += 1;
         None
     }
+}
+
+/// Correlation state for a single template.
+struct Detector {
+    template: Template,
+    buffer: Buffer,
+    fft: Option<FftCorrelator>,
+    /// Window energies at the hops the FFT correlator hasn't produced yet
+    pending_energies: VecDeque<f32>,
+    hop_phase: usize,
+    fft_hop_phase: usize,
+    armed: bool,
+    background: RunningStats,
+    /// Samples covered by the correlations passed to `determine_event` so far
+    samples_processed: u64,
+    previous: f32,
+    /// Best match so far while waiting out the look-ahead
+    peak: Option<Peak>,
+    last_peak_end: Option<u64>,
+}
+
+/// The highest score of a match, along with its neighbours for interpolation.
+struct Peak {
+    score: f32,
+    before: f32,
+    after: Option<f32>,
+    /// Sample position at which the window producing `score` ended
+    end: u64,
+}
+
+impl Detector {
+    fn new(template: Template, method: CorrelationMethod) -> Self {
+        let fft = match method {
+            CorrelationMethod::Naive => None,
+            CorrelationMethod::Fft => Some(FftCorrelator::new(&template.target, FFT_BLOCK_SIZE)),
+        };
+        Self {
+            buffer: Buffer::new(template.target.len()),
+            template,
+            fft,
+            pending_energies: VecDeque::new(),
+            hop_phase: 0,
+            fft_hop_phase: 0,
+            armed: true,
+            background: RunningStats::default(),
+            samples_processed: 0,
+            previous: 0.0,
+            peak: None,
+            last_peak_end: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.buffer = Buffer::new(self.template.target.len());
+        if let Some(fft) = &mut self.fft {
+            fft.reset();
+        }
+        self.pending_energies.clear();
+        self.hop_phase = 0;
+        self.fft_hop_phase = 0;
+        self.armed = true;
+        self.background = RunningStats::default();
+        self.samples_processed = 0;
+        self.previous = 0.0;
+        self.peak = None;
+        self.last_peak_end = None;
+    }
+
+    fn correlate(
+        &mut self,
+        chunk: &[f32],
+        hop: usize,
+        normalization: Normalization,
+    ) -> impl Iterator<Item = f32> {
+        let mut correlations = Vec::new();
+        let mut rest = chunk;
+        while !rest.is_empty() {
+            let (head, tail) = rest.split_at((hop - self.hop_phase).min(rest.len()));
+            self.buffer.process_chunk(head);
+            self.hop_phase += head.len();
+            if self.hop_phase == hop {
+                self.hop_phase = 0;
+                if self.fft.is_some() {
+                    self.pending_energies.push_back(self.buffer.energy());
+                } else {
+                    correlations.push(self.compute_correlation(normalization));
+                }
+            }
+            rest = tail;
+        }
+
+        if let Some(fft) = &mut self.fft {
+            for y in fft.push(chunk) {
+                self.fft_hop_phase += 1;
+                if self.fft_hop_phase == hop {
+                    self.fft_hop_phase = 0;
+                    let energy = self.pending_energies.pop_front().unwrap();
+                    correlations.push(self.template.target.normalize(y, energy, normalization));
+                }
+            }
+        }
+        correlations.into_iter()
+    }
+
+    /// Reports the pending peak once no higher score has been seen for `lookahead` samples.
+    fn report_peak_after(&mut self, lookahead: u64, hop: u64, clock: &Clock) -> Option<FroskEvent> {
+        let peak = self.peak.as_ref()?;
+        if self.samples_processed - peak.end < lookahead {
+            return None;
+        }
+
+        // fit a parabola through the peak and its neighbours to place the
+        // match between two hops
+        let mut end = peak.end as f32;
+        if let Some(after) = peak.after {
+            let curvature = peak.before - 2.0 * peak.score + after;
+            if curvature < 0.0 {
+                end += 0.5 * (peak.before - after) / curvature * hop as f32;
+            }
+        }
+        let sample_rate = self.template.target.sample_rate();
+        let (captured_at, wall_clock) = clock.time_of(self.samples_processed, sample_rate);
+        let detection = Detection {
+            score: peak.score,
+            sample_offset: (end.round() as u64).saturating_sub(self.template.target.len() as u64),
+            sample_index: self.samples_processed,
+            captured_at,
+            wall_clock,
+        };
+        self.last_peak_end = Some(peak.end);
+        self.peak = None;
+        Some(self.template.kind.event(detection))
+    }
 
-    pub fn compute_correlation(&self) -> f32 {
-        self.buffer
+    fn compute_correlation(&self, normalization: Normalization) -> f32 {
+        let dot = self
+            .buffer
             .rb_cons
             .iter()
-            .zip(self.target.target.iter())
+            .zip(self.template.target.target.iter())
             .map(|(a, b)| a * b)
-            .sum::<f32>()
-            / self.target.norm
+            .sum::<f32>();
+        self.template
+            .target
+            .normalize(dot, self.buffer.energy(), normalization)
     }
 }
 
-// TODO: find a nicer way to express this
-type RbProdType =
-    Caching<Arc<SharedRb<Owning<[MaybeUninit<f32>; TARGET_SAMPLE_COUNT]>>>, true, false>;
-type RbConsType =
-    Caching<Arc<SharedRb<Owning<[MaybeUninit<f32>; TARGET_SAMPLE_COUNT]>>>, false, true>;
-struct Buffer {
-    rb_prod: RbProdType,
-    rb_cons: RbConsType,
+/// Maps positions in the processed stream to the time they were captured.
+struct Clock {
+    /// Number of samples pushed so far, and when the last of them was captured
+    latest: Option<(u64, Instant)>,
+    /// A pair of readings used to translate instants into wall-clock time
+    reference: (Instant, SystemTime),
 }
 
-impl Default for Buffer {
+impl Default for Clock {
     fn default() -> Self {
-        let ringbuffer = StaticRb::<f32, TARGET_SAMPLE_COUNT>::default();
-        let (mut rb_prod, rb_cons) = ringbuffer.split();
-        for _ in 0..TARGET_SAMPLE_COUNT {
-            rb_prod.try_push(0.0).unwrap();
+        Self {
+            latest: None,
+            reference: (Instant::now(), SystemTime::now()),
         }
-        Self { rb_prod, rb_cons }
     }
 }
 
+impl Clock {
+    fn advance(&mut self, samples: u64, captured_at: Instant) {
+        let pushed = self.latest.map_or(0, |(pushed, _)| pushed);
+        self.latest = Some((pushed + samples, captured_at));
+    }
+
+    /// Returns when the sample at `sample_index` was captured, assuming the
+    /// stream had no gaps.
+    fn time_of(&self, sample_index: u64, sample_rate: u32) -> (Instant, SystemTime) {
+        let captured_at = match self.latest {
+            Some((pushed, captured_at)) => {
+                let behind = pushed.saturating_sub(sample_index);
+                let behind = Duration::from_secs_f64(behind as f64 / sample_rate as f64);
+                captured_at.checked_sub(behind).unwrap_or(captured_at)
+            }
+            None => Instant::now(),
+        };
+        let (reference_instant, reference_time) = self.reference;
+        let wall_clock = match captured_at.checked_duration_since(reference_instant) {
+            Some(elapsed) => reference_time + elapsed,
+            None => reference_time - reference_instant.duration_since(captured_at),
+        };
+        (captured_at, wall_clock)
+    }
+}
+
+/// Exponentially weighted mean and variance of a correlation stream.
+#[derive(Default)]
+struct RunningStats {
+    mean: f32,
+    variance: f32,
+    /// Number of samples the statistics are based on
+    samples: u64,
+}
+
+impl RunningStats {
+    /// Adds a value covering `samples` samples, forgetting values older than
+    /// roughly `window` samples. Until a full window has been seen this is a
+    /// plain average, so the statistics settle quickly.
+    fn push(&mut self, value: f32, samples: u64, window: u64) {
+        self.samples += samples;
+        let alpha = (samples as f32 / self.samples.min(window.max(1)) as f32).min(1.0);
+        let diff = value - self.mean;
+        let increment = alpha * diff;
+        self.mean += increment;
+        self.variance = (1.0 - alpha) * (self.variance + diff * increment);
+    }
+}
+
+/// Correlates a stream with the target by filtering it with the time-reversed
+/// target, using uniformly partitioned overlap-save convolution: the filter is
+/// split into partitions of `block` samples, whose spectra are multiplied with
+/// a delay line holding the spectra of the most recent input blocks.
+struct FftCorrelator {
+    block: usize,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    partitions: Vec<Vec<Complex<f32>>>,
+    delay_line: Vec<Vec<Complex<f32>>>,
+    newest: usize,
+    previous: Vec<f32>,
+    input: Vec<f32>,
+    frame: Vec<Complex<f32>>,
+    output: Vec<f32>,
+}
+
+impl FftCorrelator {
+    fn new(target: &Target, block: usize) -> Self {
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(2 * block);
+        let ifft = planner.plan_fft_inverse(2 * block);
+
+        let reversed: Vec<f32> = target.target.iter().rev().copied().collect();
+        let partitions: Vec<Vec<Complex<f32>>> = reversed
+            .chunks(block)
+            .map(|partition| {
+                let mut spectrum = vec![Complex::default(); 2 * block];
+                for (bin, h) in spectrum.iter_mut().zip(partition) {
+                    *bin = Complex::from(h);
+                }
+                fft.process(&mut spectrum);
+                spectrum
+            })
+            .collect();
+        let delay_line = vec![vec![Complex::default(); 2 * block]; partitions.len()];
+
+        Self {
+            block,
+            fft,
+            ifft,
+            partitions,
+            delay_line,
+            newest: 0,
+            previous: vec![0.0; block],
+            input: Vec::with_capacity(block),
+            frame: vec![Complex::default(); 2 * block],
+            output: Vec::new(),
+        }
+    }
+
+    fn reset(&mut self) {
+        for spectrum in &mut self.delay_line {
+            spectrum.fill(Complex::default());
+        }
+        self.newest = 0;
+        self.previous.fill(0.0);
+        self.input.clear();
+    }
+
+    /// Pushes `chunk` and returns the filter output for every sample of the
+    /// blocks completed by it.
+    fn push(&mut self, chunk: &[f32]) -> Vec<f32> {
+        self.output.clear();
+        let mut rest = chunk;
+        while !rest.is_empty() {
+            let (head, tail) = rest.split_at((self.block - self.input.len()).min(rest.len()));
+            self.input.extend_from_slice(head);
+            if self.input.len() == self.block {
+                self.process_block();
+            }
+            rest = tail;
+        }
+        std::mem::take(&mut self.output)
+    }
+
+    fn process_block(&mut self) {
+        let n = self.delay_line.len();
+        self.newest = (self.newest + 1) % n;
+
+        let spectrum = &mut self.delay_line[self.newest];
+        for (bin, x) in spectrum
+            .iter_mut()
+            .zip(self.previous.iter().chain(self.input.iter()))
+        {
+            *bin = Complex::from(x);
+        }
+        self.fft.process(spectrum);
+
+        self.frame.fill(Complex::default());
+        for (p, partition) in self.partitions.iter().enumerate() {
+            let spectrum = &self.delay_line[(self.newest + n - p) % n];
+            for ((acc, x), h) in self.frame.iter_mut().zip(spectrum).zip(partition) {
+                *acc += x * h;
+            }
+        }
+        self.ifft.process(&mut self.frame);
+
+        // only the second half of the frame is free of circular wrap-around
+        let scale = 1.0 / self.frame.len() as f32;
+        self.output
+            .extend(self.frame[self.block..].iter().map(|y| y.re * scale));
+        std::mem::swap(&mut self.previous, &mut self.input);
+        self.input.clear();
+    }
+}
+
+struct Buffer {
+    rb_prod: HeapProd<f32>,
+    rb_cons: HeapCons<f32>,
+    /// Sum of squares of the buffered samples, accumulated in f64 to limit drift
+    energy: f64,
+}
+
 impl Buffer {
+    fn new(len: usize) -> Self {
+        let ringbuffer = HeapRb::<f32>::new(len);
+        let (mut rb_prod, rb_cons) = ringbuffer.split();
+        for _ in 0..len {
+            rb_prod.try_push(0.0).unwrap();
+        }
+        Self {
+            rb_prod,
+            rb_cons,
+            energy: 0.0,
+        }
+    }
+
+    /// Pushes `chunk`, of which only the last window's worth of samples stays buffered.
     pub fn process_chunk(&mut self, chunk: &[f32]) {
+        // pushing more than the buffer holds would overflow it and throw off the energy
+        for piece in chunk.chunks(self.rb_cons.capacity().get()) {
+            self.process_piece(piece);
+        }
+    }
+
+    fn process_piece(&mut self, chunk: &[f32]) {
+        let removed = self
+            .rb_cons
+            .iter()
+            .take(chunk.len())
+            .map(|x| (x * x) as f64)
+            .sum::<f64>();
+        let added = chunk.iter().map(|x| (x * x) as f64).sum::<f64>();
+        self.energy = (self.energy + added - removed).max(0.0);
+
         self.rb_cons.skip(chunk.len());
         self.rb_prod.push_slice(chunk);
     }
+
+    pub fn energy(&self) -> f32 {
+        self.energy as f32
+    }
 }
 
-struct Target {
+/// The sound the captured audio is correlated with, e.g. the splash of a fish biting.
+#[derive(Clone)]
+pub struct Target {
     target: Vec<f32>,
+    sample_rate: u32,
     norm: f32,
 }
 
 impl Default for Target {
+    /// The fish bite sound bundled with frosk.
     fn default() -> Self {
-        let buf_reader = std::io::Cursor::new(TARGET_BYTES);
-        let target: Vec<f32> = hound::WavReader::new(buf_reader)
-            .unwrap()
-            .samples::<i32>()
-            .map(|s| s.unwrap() as f32 / i32::MAX as f32)
-            .collect();
+        Self::from_wav_bytes(TARGET_BYTES).expect("bundled target is a valid WAV file")
+    }
+}
+
+impl Target {
+    pub fn from_wav_file(path: impl AsRef<Path>) -> Result<Self> {
+        let (target, sample_rate) = decode_wav(BufReader::new(File::open(path)?))?;
+        Ok(Self::from_samples(target, sample_rate))
+    }
+
+    pub fn from_wav_bytes(bytes: &[u8]) -> Result<Self> {
+        let (target, sample_rate) = decode_wav(bytes)?;
+        Ok(Self::from_samples(target, sample_rate))
+    }
+
+    pub fn from_samples(target: Vec<f32>, sample_rate: u32) -> Self {
+        assert!(!target.is_empty(), "target must contain samples");
         let norm = target.iter().map(|x| x.powi(2)).sum::<f32>();
-        Self { target, norm }
+        Self {
+            target,
+            sample_rate,
+            norm,
+        }
+    }
+
+    /// The same sound at `sample_rate`.
+    pub fn resampled(&self, sample_rate: u32) -> Self {
+        let mut target = Vec::new();
+        Resampler::new(self.sample_rate, sample_rate).process(&self.target, &mut target);
+        if target.is_empty() {
+            // a single sample has nothing to interpolate with
+            target.push(self.target[0]);
+        }
+        Self::from_samples(target, sample_rate)
+    }
+
+    /// Number of samples in the target, which is also the length of the buffered window.
+    pub fn len(&self) -> usize {
+        self.target.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.target.is_empty()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn normalize(&self, dot: f32, window_energy: f32, normalization: Normalization) -> f32 {
+        match normalization {
+            Normalization::Target => dot / self.norm,
+            Normalization::TargetAndWindow => {
+                let denominator = (self.norm * window_energy).sqrt();
+                if denominator > f32::EPSILON {
+                    dot / denominator
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TemplateKind {
+    FishBite,
+    CastSplash,
+    BobberLanded,
+    NoFishHooked,
+    InventoryFull,
+}
+
+impl TemplateKind {
+    pub const ALL: [TemplateKind; 5] = [
+        TemplateKind::FishBite,
+        TemplateKind::CastSplash,
+        TemplateKind::BobberLanded,
+        TemplateKind::NoFishHooked,
+        TemplateKind::InventoryFull,
+    ];
+
+    pub fn event(self, detection: Detection) -> FroskEvent {
+        match self {
+            TemplateKind::FishBite => FroskEvent::FishBite(detection),
+            TemplateKind::CastSplash => FroskEvent::CastSplash(detection),
+            TemplateKind::BobberLanded => FroskEvent::BobberLanded(detection),
+            TemplateKind::NoFishHooked => FroskEvent::NoFishHooked(detection),
+            TemplateKind::InventoryFull => FroskEvent::InventoryFull(detection),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TemplateKind::FishBite => "fish-bite",
+            TemplateKind::CastSplash => "cast-splash",
+            TemplateKind::BobberLanded => "bobber-landed",
+            TemplateKind::NoFishHooked => "no-fish-hooked",
+            TemplateKind::InventoryFull => "inventory-full",
+        }
+    }
+}
+
+impl fmt::Display for TemplateKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for TemplateKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| anyhow!("unknown template kind: {s}"))
+    }
+}
+
+/// A named target whose matches are reported as events of the given kind.
+/// Several templates may share a kind, e.g. bite sounds from different patches.
+#[derive(Clone)]
+pub struct Template {
+    pub name: String,
+    pub kind: TemplateKind,
+    pub target: Target,
+}
+
+impl Default for Template {
+    /// The fish bite sound bundled with frosk.
+    fn default() -> Self {
+        Self::new(
+            TemplateKind::FishBite.name(),
+            TemplateKind::FishBite,
+            Target::default(),
+        )
+    }
+}
+
+impl Template {
+    pub fn new(name: impl Into<String>, kind: TemplateKind, target: Target) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            target,
+        }
     }
 }
 
@@ -120,6 +860,7 @@ mod tests {
     use std::collections::VecDeque;
 
     use super::*;
+    use ringbuf::StaticRb;
 
     #[test]
     fn test_deque_slice() {
@@ -165,4 +906,387 @@ mod tests {
             assert_eq!(*x, (i + 11) as f32);
         });
     }
+
+    #[test]
+    fn test_fft_correlator_matches_direct_correlation() {
+        let target = Target::from_samples(
+            (0..150).map(|_| rand::random::<f32>() - 0.5).collect(),
+            44100,
+        );
+        let source: Vec<f32> = (0..1000).map(|_| rand::random::<f32>() - 0.5).collect();
+
+        let mut correlator = FftCorrelator::new(&target, 64);
+        let output: Vec<f32> = source
+            .chunks(37)
+            .flat_map(|chunk| correlator.push(chunk))
+            .collect();
+        // only whole blocks have been processed
+        assert_eq!(output.len(), 960);
+
+        let n = target.target.len();
+        for (i, y) in output.iter().enumerate() {
+            let expected: f32 = (0..n)
+                .filter(|k| i + k + 1 >= n)
+                .map(|k| source[i + k + 1 - n] * target.target[k])
+                .sum();
+            assert!((y - expected).abs() < 1e-4, "sample {i}: {y} != {expected}");
+        }
+    }
+
+    #[test]
+    fn test_fft_method_matches_naive_method() {
+        let source: Vec<f32> = (0..3 * FFT_BLOCK_SIZE)
+            .map(|_| rand::random::<f32>() - 0.5)
+            .collect();
+
+        let mut naive = SignalProcessor::new(CorrelationMethod::Naive);
+        let mut fft = SignalProcessor::new(CorrelationMethod::Fft);
+        let expected: Vec<f32> = source
+            .chunks(440)
+            .flat_map(|c| naive.correlate(c, Instant::now()))
+            .flatten()
+            .collect();
+        let actual: Vec<f32> = source
+            .chunks(440)
+            .flat_map(|c| fft.correlate(c, Instant::now()))
+            .flatten()
+            .collect();
+
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(&expected) {
+            assert!((a - e).abs() < 1e-3, "{a} != {e}");
+        }
+    }
+
+    #[test]
+    fn test_reset_processor_matches_a_fresh_one() {
+        let source: Vec<f32> = (0..3 * FFT_BLOCK_SIZE)
+            .map(|_| rand::random::<f32>() - 0.5)
+            .collect();
+        let mut signal_processor = SignalProcessor::new(CorrelationMethod::Fft);
+        let run = |signal_processor: &mut SignalProcessor| -> Vec<f32> {
+            source
+                .chunks(440)
+                .flat_map(|c| signal_processor.correlate(c, Instant::now()))
+                .flatten()
+                .collect()
+        };
+
+        let fresh = run(&mut signal_processor);
+        signal_processor.reset();
+        assert_eq!(run(&mut signal_processor), fresh);
+        signal_processor.reset();
+        assert!(signal_processor.add_template(Template::default()).is_ok());
+    }
+
+    #[test]
+    fn test_buffer_energy_tracks_window() {
+        let mut buffer = Buffer::new(20_000);
+        for _ in 0..(3 * 20_000 / 440) {
+            let chunk: Vec<f32> = (0..440).map(|_| rand::random::<f32>() - 0.5).collect();
+            buffer.process_chunk(&chunk);
+        }
+        let expected = buffer.rb_cons.iter().map(|x| x * x).sum::<f32>();
+        assert!((buffer.energy() - expected).abs() / expected < 1e-4);
+    }
+
+    #[test]
+    fn test_buffer_keeps_the_end_of_chunks_longer_than_the_window() {
+        let mut buffer = Buffer::new(4);
+        buffer.process_chunk(&[1.0; 3]);
+        buffer.process_chunk(&[0.5, 0.5, 0.5, 0.5, 0.5, 0.5, 2.0, 1.0, 0.0]);
+        assert_eq!(
+            buffer.rb_cons.iter().copied().collect::<Vec<_>>(),
+            [0.5, 2.0, 1.0, 0.0]
+        );
+        assert!((buffer.energy() - 5.25).abs() < 1e-6);
+
+        // a hop longer than the template feeds the buffer such chunks
+        let target = Target::from_samples(vec![1.0; 3], 8000);
+        let template = Template::new("bite", TemplateKind::FishBite, target);
+        let mut signal_processor =
+            SignalProcessor::from_templates([template], CorrelationMethod::Naive)
+                .with_hop(8)
+                .with_normalization(Normalization::TargetAndWindow);
+        let correlations = signal_processor.correlate(&[1.0; 64], Instant::now());
+        assert!(correlations.iter().all(|c| (c[0] - 1.0).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_window_normalization_ignores_volume() {
+        let quiet_target: Vec<f32> = Target::default().target.iter().map(|x| 0.1 * x).collect();
+        let mut source = vec![0.0; 8];
+        source.extend(&quiet_target);
+        source.extend(vec![0.0; 2 * FFT_BLOCK_SIZE]);
+
+        let peak = |normalization| {
+            let mut signal_processor =
+                SignalProcessor::new(CorrelationMethod::Fft).with_normalization(normalization);
+            source
+                .chunks(440)
+                .flat_map(|c| signal_processor.correlate(c, Instant::now()))
+                .flatten()
+                .fold(f32::MIN, f32::max)
+        };
+        assert!((peak(Normalization::Target) - 0.1).abs() < 1e-3);
+        assert!((peak(Normalization::TargetAndWindow) - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_processor_buffer_follows_runtime_target() {
+        let target = Target::from_samples(vec![0.5, -0.5, 0.25], 8000);
+        let template = Template::new("bite", TemplateKind::FishBite, target);
+        let mut signal_processor =
+            SignalProcessor::from_templates([template], CorrelationMethod::Naive).with_hop(1);
+        let correlations = signal_processor.correlate(&[0.0, 0.5, -0.5, 0.25, 0.0], Instant::now());
+        assert_eq!(correlations.len(), 5);
+        assert_eq!(correlations[3], vec![1.0]);
+        assert_eq!(
+            signal_processor.detectors[0].buffer.rb_cons.occupied_len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_templates_emit_their_own_events() {
+        let bite = Target::from_samples(vec![0.5, -0.5, -0.25], 8000);
+        let splash = Target::from_samples(vec![1.0; 5], 8000);
+        let mut signal_processor = SignalProcessor::from_templates(
+            [
+                Template::new("bite", TemplateKind::FishBite, bite),
+                Template::new("splash", TemplateKind::CastSplash, splash),
+            ],
+            CorrelationMethod::Naive,
+        )
+        .with_hop(1)
+        .with_detector_config(DetectorConfig {
+            peak_lookahead_ms: 0,
+            ..DetectorConfig::default()
+        });
+
+        let mut source = vec![0.0; 60];
+        source.extend([1.0; 5]);
+        let mut events = Vec::new();
+        for correlations in signal_processor.correlate(&source, Instant::now()) {
+            assert_eq!(correlations.len(), 2);
+            for (template, correlation) in correlations.into_iter().enumerate() {
+                events.extend(signal_processor.determine_event(template, correlation));
+            }
+        }
+
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0], FroskEvent::CastSplash(_)));
+    }
+
+    #[test]
+    fn test_templates_cannot_be_added_after_audio() {
+        let target = Target::from_samples(vec![1.0; 3], 8000);
+        let mut signal_processor = SignalProcessor::from_templates([], CorrelationMethod::Naive);
+        signal_processor
+            .add_template(Template::new(
+                "bite",
+                TemplateKind::FishBite,
+                target.clone(),
+            ))
+            .unwrap();
+        signal_processor.correlate(&[0.0; 15], Instant::now());
+        assert!(signal_processor
+            .add_template(Template::new("splash", TemplateKind::CastSplash, target))
+            .is_err());
+        assert_eq!(signal_processor.templates().count(), 1);
+    }
+
+    #[test]
+    fn test_templates_are_resampled_to_the_first_rate() {
+        let signal_processor = SignalProcessor::from_templates(
+            [
+                Template::new(
+                    "bite",
+                    TemplateKind::FishBite,
+                    Target::from_samples(vec![1.0; 441], 44100),
+                ),
+                Template::new(
+                    "splash",
+                    TemplateKind::CastSplash,
+                    Target::from_samples(vec![1.0; 480], 48000),
+                ),
+            ],
+            CorrelationMethod::Naive,
+        );
+        assert_eq!(signal_processor.sample_rate(), 44100);
+        let splash = signal_processor.templates().nth(1).unwrap();
+        assert_eq!(splash.target.sample_rate(), 44100);
+        assert!((splash.target.len() as i64 - 441).abs() <= 1);
+    }
+
+    #[test]
+    fn test_template_kind_round_trips_through_name() {
+        for kind in TemplateKind::ALL {
+            assert_eq!(kind.name().parse::<TemplateKind>().unwrap(), kind);
+        }
+        assert!("bite".parse::<TemplateKind>().is_err());
+    }
+
+    #[test]
+    fn test_detector_rearms_and_respects_refractory_period() {
+        let target = Target::from_samples(vec![1.0], 1000);
+        let template = Template::new("blip", TemplateKind::FishBite, target);
+        let mut signal_processor =
+            SignalProcessor::from_templates([template], CorrelationMethod::Naive)
+                .with_hop(10)
+                .with_detector_config(DetectorConfig {
+                    threshold: 0.5,
+                    rearm_level: 0.2,
+                    refractory_ms: 100,
+                    peak_lookahead_ms: 0,
+                    ..DetectorConfig::default()
+                });
+        let mut fired = |scores: &[f32]| -> Vec<usize> {
+            scores
+                .iter()
+                .enumerate()
+                .filter_map(|(i, &score)| signal_processor.determine_event(0, score).map(|_| i))
+                .collect()
+        };
+
+        // stays disarmed while the score hovers between the re-arm level and the threshold
+        assert_eq!(fired(&[0.6, 0.4, 0.6, 0.4]), vec![0]);
+        // re-armed, but each hop is 10ms so the 100ms refractory period hasn't passed yet
+        assert_eq!(fired(&[0.1, 0.6]), Vec::<usize>::new());
+        assert_eq!(fired(&[0.1; 5]), Vec::<usize>::new());
+        assert_eq!(fired(&[0.6]), vec![0]);
+    }
+
+    #[test]
+    fn test_running_stats_follow_stream() {
+        let mut stats = RunningStats::default();
+        for i in 0..10_000 {
+            let value = if i % 2 == 0 { 1.0 } else { 3.0 };
+            stats.push(value, 10, 10_000);
+        }
+        assert!((stats.mean - 2.0).abs() < 0.05);
+        assert!((stats.variance - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_adaptive_threshold_scales_with_background() {
+        let config = DetectorConfig {
+            threshold_mode: ThresholdMode::Adaptive(AdaptiveThreshold {
+                sigmas: 5.0,
+                rearm_sigmas: 2.0,
+                window_ms: 1000,
+                min_threshold: 0.0,
+            }),
+            peak_lookahead_ms: 0,
+            ..DetectorConfig::default()
+        };
+        let fires_on_peak = |noise: f32, peak: f32| {
+            let target = Target::from_samples(vec![1.0], 1000);
+            let template = Template::new("blip", TemplateKind::FishBite, target);
+            let mut signal_processor =
+                SignalProcessor::from_templates([template], CorrelationMethod::Naive)
+                    .with_detector_config(config);
+            // two seconds of background noise, then the peak
+            let fired_early = (0..200)
+                .map(|_| noise * (rand::random::<f32>() - 0.5))
+                .any(|score| signal_processor.determine_event(0, score).is_some());
+            assert!(!fired_early);
+            signal_processor.determine_event(0, peak).is_some()
+        };
+
+        assert!(fires_on_peak(0.01, 0.1));
+        assert!(!fires_on_peak(0.5, 0.1));
+    }
+
+    #[test]
+    fn test_adaptive_threshold_follows_rising_noise_floor() {
+        let target = Target::from_samples(vec![1.0], 1000);
+        let template = Template::new("blip", TemplateKind::FishBite, target);
+        let mut signal_processor =
+            SignalProcessor::from_templates([template], CorrelationMethod::Naive)
+                .with_detector_config(DetectorConfig {
+                    threshold_mode: ThresholdMode::Adaptive(AdaptiveThreshold {
+                        sigmas: 5.0,
+                        rearm_sigmas: 2.0,
+                        window_ms: 1000,
+                        min_threshold: 0.0,
+                    }),
+                    refractory_ms: 200,
+                    peak_lookahead_ms: 0,
+                    ..DetectorConfig::default()
+                });
+        // deterministic noise in [-0.5, 0.5), with hops of 10ms
+        let noise = |i: usize| ((i as f32 * 12.9898).sin() * 43758.547).fract().abs() - 0.5;
+
+        let quiet = (0..200).map(|i| 0.01 * noise(i));
+        // the background rises well above the threshold for ten seconds, e.g. when music starts
+        let loud = (200..1200).map(|i| 0.05 + 0.01 * noise(i));
+        let events = quiet
+            .chain(loud)
+            .filter(|&score| signal_processor.determine_event(0, score).is_some())
+            .count();
+        // the jump itself stands out, but the threshold catches up within the window
+        assert_eq!(events, 1);
+        // and a bite on top of the louder background is still heard
+        assert!(signal_processor.determine_event(0, 0.2).is_some());
+    }
+
+    #[test]
+    fn test_event_reports_interpolated_peak() {
+        let target = Target::from_samples(vec![1.0], 1000);
+        let template = Template::new("blip", TemplateKind::FishBite, target);
+        let mut signal_processor =
+            SignalProcessor::from_templates([template], CorrelationMethod::Naive)
+                .with_hop(10)
+                .with_detector_config(DetectorConfig {
+                    threshold: 0.3,
+                    peak_lookahead_ms: 30,
+                    ..DetectorConfig::default()
+                });
+
+        let scores = [0.1, 0.4, 0.6, 0.9, 0.7, 0.5, 0.2, 0.1];
+        let events: Vec<(usize, FroskEvent)> = scores
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &score)| signal_processor.determine_event(0, score).map(|e| (i, e)))
+            .collect();
+
+        // the peak ends at sample 40 and is reported 30 samples later, shifted
+        // a tenth of a hop towards its larger neighbour
+        assert_eq!(events.len(), 1);
+        let (i, event) = events[0];
+        assert_eq!(i, 6);
+        assert_eq!(event.detection().unwrap().score, 0.9);
+        assert_eq!(event.detection().unwrap().sample_offset, 40);
+    }
+
+    #[test]
+    fn test_events_are_stamped_with_capture_time() {
+        let target = Target::from_samples(vec![1.0], 1000);
+        let template = Template::new("blip", TemplateKind::FishBite, target);
+        let mut signal_processor =
+            SignalProcessor::from_templates([template], CorrelationMethod::Naive)
+                .with_hop(1)
+                .with_detector_config(DetectorConfig {
+                    peak_lookahead_ms: 0,
+                    ..DetectorConfig::default()
+                });
+
+        let mut chunk = vec![0.0; 100];
+        chunk[79] = 1.0;
+        let captured_at = Instant::now();
+        let mut events = Vec::new();
+        for correlations in signal_processor.correlate(&chunk, captured_at) {
+            events.extend(signal_processor.determine_event(0, correlations[0]));
+        }
+
+        // the match ends 20 samples, i.e. 20ms, before the end of the chunk
+        assert_eq!(events.len(), 1);
+        let detection = events[0].detection().unwrap();
+        assert_eq!(detection.sample_index, 80);
+        assert_eq!(
+            captured_at.duration_since(detection.captured_at),
+            Duration::from_millis(20)
+        );
+    }
 }
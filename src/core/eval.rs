@@ -0,0 +1,432 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    capture::file::{FileCapturer, ReplaySpeed},
+    dsp::{AdaptiveThreshold, DetectorConfig, SignalProcessor, ThresholdMode},
+    event::{Detection, FroskEvent},
+};
+
+/// A recording along with the times at which a fish bite starts in it.
+///
+/// The bites are read from a label file next to the recording with the same
+/// name and a `.txt` extension, holding one time in seconds per line. Audacity
+/// label tracks exported as text work as they are, since only the first
+/// column is read. An empty label file marks a recording without bites, which
+/// still counts towards the false alarms.
+pub struct LabeledRecording {
+    pub path: PathBuf,
+    pub capturer: FileCapturer,
+    /// Seconds into the recording at which each bite sound starts
+    pub bites: Vec<f64>,
+}
+
+impl LabeledRecording {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let capturer =
+            FileCapturer::open(&path).with_context(|| format!("reading {}", path.display()))?;
+        let labels = path.with_extension("txt");
+        let contents = fs::read_to_string(&labels)
+            .with_context(|| format!("reading the labels of {}", path.display()))?;
+        let bites =
+            parse_labels(&contents).with_context(|| format!("parsing {}", labels.display()))?;
+        Ok(Self {
+            path,
+            capturer,
+            bites,
+        })
+    }
+
+    /// Length of the recording in seconds.
+    pub fn duration(&self) -> f64 {
+        self.capturer.samples().len() as f64 / self.capturer.sample_rate() as f64
+    }
+}
+
+/// Opens every WAV and FLAC recording in `dir`, sorted by name.
+pub fn load_dir(dir: &Path) -> Result<Vec<LabeledRecording>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("reading {}", dir.display()))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<_>>()?;
+    paths.retain(|path| {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("wav") || e.eq_ignore_ascii_case("flac"))
+    });
+    paths.sort();
+    if paths.is_empty() {
+        bail!("no recordings in {}", dir.display());
+    }
+    paths.into_iter().map(LabeledRecording::open).collect()
+}
+
+/// Reads the first column of each line as seconds, skipping blank lines and `#` comments.
+pub fn parse_labels(contents: &str) -> Result<Vec<f64>> {
+    let mut bites = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let field = line.split_whitespace().next().unwrap_or_default();
+        let time: f64 = field
+            .parse()
+            .with_context(|| format!("line {}: expected seconds, got {field:?}", number + 1))?;
+        bites.push(time);
+    }
+    bites.sort_by(f64::total_cmp);
+    Ok(bites)
+}
+
+/// The correlation streams of a recording, which don't depend on the detector
+/// config, so a sweep only has to correlate each recording once.
+pub struct Correlated {
+    bites: Vec<f64>,
+    duration: f64,
+    sample_rate: u32,
+    templates: usize,
+    /// Correlation with each template at every hop, `templates` values per hop
+    scores: Vec<f32>,
+}
+
+impl Correlated {
+    /// Replays `recording` through `signal_processor` in chunks of `chunk_size`,
+    /// as the CLI does with `--file`, after resetting it. The recording is
+    /// resampled to the rate of the templates first.
+    pub fn new(
+        recording: &LabeledRecording,
+        signal_processor: &mut SignalProcessor,
+        chunk_size: usize,
+    ) -> Self {
+        signal_processor.reset();
+        let sample_rate = signal_processor.sample_rate();
+        let templates = signal_processor.templates().count();
+        let mut scores = Vec::new();
+        recording
+            .capturer
+            .resampled(sample_rate)
+            .with_chunk_size(chunk_size)
+            .with_speed(ReplaySpeed::AsFastAsPossible)
+            .replay(|chunk, captured_at| {
+                for hop in signal_processor.correlate(chunk, captured_at) {
+                    scores.extend(hop);
+                }
+            });
+        Self {
+            bites: recording.bites.clone(),
+            duration: recording.duration(),
+            sample_rate,
+            templates,
+            scores,
+        }
+    }
+
+    /// Resets `signal_processor` and runs its detector over the correlations,
+    /// returning the fish bites it reports.
+    pub fn detect(&self, signal_processor: &mut SignalProcessor) -> Vec<Detection> {
+        signal_processor.reset();
+        let mut bites = Vec::new();
+        for (i, &correlation) in self.scores.iter().enumerate() {
+            if let Some(FroskEvent::FishBite(detection)) =
+                signal_processor.determine_event(i % self.templates, correlation)
+            {
+                bites.push(detection);
+            }
+        }
+        bites
+    }
+
+    /// Compares `detections` with the labelled bites. A detection whose match
+    /// starts within `tolerance` seconds of a bite counts for the nearest bite
+    /// that hasn't been detected yet, and as a false alarm otherwise.
+    pub fn score(&self, detections: &[Detection], tolerance: f64) -> Metrics {
+        let sample_rate = self.sample_rate as f64;
+        let mut detected = vec![false; self.bites.len()];
+        let mut metrics = Metrics {
+            duration: self.duration,
+            ..Metrics::default()
+        };
+        for detection in detections {
+            let start = detection.sample_offset as f64 / sample_rate;
+            let nearest = self
+                .bites
+                .iter()
+                .enumerate()
+                .filter(|&(i, &bite)| !detected[i] && (start - bite).abs() <= tolerance)
+                .min_by(|(_, a), (_, b)| (start - **a).abs().total_cmp(&(start - **b).abs()));
+            match nearest {
+                Some((i, &bite)) => {
+                    detected[i] = true;
+                    metrics.true_positives += 1;
+                    metrics
+                        .latencies
+                        .push(detection.sample_index as f64 / sample_rate - bite);
+                }
+                None => metrics.false_positives += 1,
+            }
+        }
+        metrics.false_negatives = detected.iter().filter(|&&detected| !detected).count();
+        metrics
+    }
+}
+
+/// How well the detector found the labelled bites.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+    /// Seconds of audio evaluated
+    pub duration: f64,
+    /// Seconds from the start of each detected bite until it was reported
+    pub latencies: Vec<f64>,
+}
+
+impl Metrics {
+    /// Adds up the results of several recordings.
+    pub fn add(&mut self, other: &Metrics) {
+        self.true_positives += other.true_positives;
+        self.false_positives += other.false_positives;
+        self.false_negatives += other.false_negatives;
+        self.duration += other.duration;
+        self.latencies.extend(&other.latencies);
+    }
+
+    /// Share of the detections that were bites, 1 without any detections.
+    pub fn precision(&self) -> f64 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_positives,
+        )
+    }
+
+    /// Share of the bites that were detected, 1 without any bites.
+    pub fn recall(&self) -> f64 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_negatives,
+        )
+    }
+
+    pub fn f1(&self) -> f64 {
+        let (precision, recall) = (self.precision(), self.recall());
+        if precision + recall == 0.0 {
+            return 0.0;
+        }
+        2.0 * precision * recall / (precision + recall)
+    }
+
+    pub fn false_alarms_per_hour(&self) -> f64 {
+        if self.duration == 0.0 {
+            return 0.0;
+        }
+        self.false_positives as f64 * 3600.0 / self.duration
+    }
+
+    /// The latency below which `percentile` percent of the detections were
+    /// reported, or `None` without any detections.
+    pub fn latency_percentile(&self, percentile: f64) -> Option<f64> {
+        let mut latencies = self.latencies.clone();
+        latencies.sort_by(f64::total_cmp);
+        let last = latencies.len().checked_sub(1)?;
+        let index = (percentile / 100.0 * last as f64).round() as usize;
+        Some(latencies[index.min(last)])
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        return 1.0;
+    }
+    numerator as f64 / denominator as f64
+}
+
+/// Sets the threshold of the active threshold mode to `value`, a correlation
+/// for fixed thresholds and standard deviations for adaptive ones, scaling the
+/// re-arm level along with it.
+pub fn with_threshold(config: DetectorConfig, value: f32) -> DetectorConfig {
+    let scaled = |rearm: f32, threshold: f32| {
+        if threshold == 0.0 {
+            rearm
+        } else {
+            rearm * value / threshold
+        }
+    };
+    match config.threshold_mode {
+        ThresholdMode::Fixed => DetectorConfig {
+            threshold: value,
+            rearm_level: scaled(config.rearm_level, config.threshold),
+            ..config
+        },
+        ThresholdMode::Adaptive(adaptive) => DetectorConfig {
+            threshold_mode: ThresholdMode::Adaptive(AdaptiveThreshold {
+                sigmas: value,
+                rearm_sigmas: scaled(adaptive.rearm_sigmas, adaptive.sigmas),
+                ..adaptive
+            }),
+            ..config
+        },
+    }
+}
+
+/// Evaluates every threshold in `thresholds` over all `recordings`, setting
+/// each in turn on the detector config of `signal_processor` with [`with_threshold`].
+/// The config is restored afterwards.
+pub fn sweep(
+    recordings: &[Correlated],
+    thresholds: &[f32],
+    tolerance: f64,
+    signal_processor: &mut SignalProcessor,
+) -> Vec<(f32, Metrics)> {
+    let detector = signal_processor.detector_config();
+    let curve = thresholds
+        .iter()
+        .map(|&threshold| {
+            signal_processor.set_detector_config(with_threshold(detector, threshold));
+            let mut metrics = Metrics::default();
+            for recording in recordings {
+                let detections = recording.detect(signal_processor);
+                metrics.add(&recording.score(&detections, tolerance));
+            }
+            (threshold, metrics)
+        })
+        .collect();
+    signal_processor.set_detector_config(detector);
+    curve
+}
+
+/// Writes the result of a sweep as CSV, one threshold per row, from which
+/// ROC (recall against false alarms per hour) and precision-recall curves can be plotted.
+pub fn curve_csv(curve: &[(f32, Metrics)]) -> String {
+    let mut csv = String::from(
+        "threshold,true_positives,false_positives,false_negatives,precision,recall,f1,\
+         false_alarms_per_hour,median_latency_ms,p90_latency_ms\n",
+    );
+    for (threshold, metrics) in curve {
+        let latency = |percentile| {
+            metrics
+                .latency_percentile(percentile)
+                .map_or(String::new(), |latency| format!("{:.0}", latency * 1000.0))
+        };
+        let _ = writeln!(
+            csv,
+            "{threshold},{},{},{},{:.4},{:.4},{:.4},{:.2},{},{}",
+            metrics.true_positives,
+            metrics.false_positives,
+            metrics.false_negatives,
+            metrics.precision(),
+            metrics.recall(),
+            metrics.f1(),
+            metrics.false_alarms_per_hour(),
+            latency(50.0),
+            latency(90.0),
+        );
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::dsp::{CorrelationMethod, Target, Template, TemplateKind};
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    fn signal_processor(threshold: f32) -> SignalProcessor {
+        let target = Target::from_samples(vec![1.0; 10], SAMPLE_RATE);
+        let template = Template::new("bite", TemplateKind::FishBite, target);
+        SignalProcessor::from_templates([template], CorrelationMethod::Naive)
+            .with_hop(1)
+            .with_detector_config(with_threshold(
+                DetectorConfig {
+                    peak_lookahead_ms: 0,
+                    refractory_ms: 0,
+                    ..DetectorConfig::default()
+                },
+                threshold,
+            ))
+    }
+
+    /// Ten seconds of silence with a loud bite at 2s, a quiet one at 5s and a
+    /// loud noise that isn't a bite at 8s.
+    fn recording() -> LabeledRecording {
+        let mut samples = vec![0.0; 10 * SAMPLE_RATE as usize];
+        for (start, level) in [(2000, 1.0), (5000, 0.4), (8000, 1.0)] {
+            samples[start..start + 10].fill(level);
+        }
+        LabeledRecording {
+            path: PathBuf::from("test.wav"),
+            capturer: FileCapturer::from_samples(samples, SAMPLE_RATE),
+            bites: vec![2.0, 5.0],
+        }
+    }
+
+    #[test]
+    fn test_labels_are_read_from_the_first_column() {
+        let labels = "# bites\n12.5\n\n3.25\t3.75\tbite\n";
+        assert_eq!(parse_labels(labels).unwrap(), vec![3.25, 12.5]);
+        assert!(parse_labels("bite at 3s").is_err());
+    }
+
+    #[test]
+    fn test_detections_are_matched_to_the_nearest_bite() {
+        let mut signal_processor = signal_processor(0.5);
+        let correlated = Correlated::new(&recording(), &mut signal_processor, 64);
+        let detections = correlated.detect(&mut signal_processor);
+        let metrics = correlated.score(&detections, 0.1);
+
+        assert_eq!(metrics.true_positives, 1);
+        assert_eq!(metrics.false_positives, 1);
+        assert_eq!(metrics.false_negatives, 1);
+        assert_eq!(metrics.precision(), 0.5);
+        assert_eq!(metrics.recall(), 0.5);
+        assert_eq!(metrics.f1(), 0.5);
+        assert_eq!(metrics.false_alarms_per_hour(), 360.0);
+        // without a look-ahead the bite is reported as soon as the score crosses the threshold
+        let latency = metrics.latency_percentile(50.0).unwrap();
+        assert!((0.0..0.02).contains(&latency), "{latency}");
+    }
+
+    #[test]
+    fn test_recordings_are_resampled_to_the_template_rate() {
+        let recording = recording();
+        let upsampled = LabeledRecording {
+            capturer: recording.capturer.resampled(2 * SAMPLE_RATE),
+            ..recording
+        };
+        let mut signal_processor = signal_processor(0.5);
+        let correlated = Correlated::new(&upsampled, &mut signal_processor, 64);
+        assert_eq!(correlated.sample_rate, SAMPLE_RATE);
+        let detections = correlated.detect(&mut signal_processor);
+        let metrics = correlated.score(&detections, 0.1);
+        assert_eq!(metrics.true_positives, 1);
+        assert_eq!(metrics.false_positives, 1);
+    }
+
+    #[test]
+    fn test_sweep_trades_false_alarms_for_recall() {
+        let mut signal_processor = signal_processor(0.5);
+        let correlated = [Correlated::new(&recording(), &mut signal_processor, 64)];
+        let curve = sweep(&correlated, &[0.2, 0.5, 2.0], 0.1, &mut signal_processor);
+        assert_eq!(signal_processor.detector_config().threshold, 0.5);
+
+        let recalls: Vec<f64> = curve.iter().map(|(_, m)| m.recall()).collect();
+        let false_alarms: Vec<usize> = curve.iter().map(|(_, m)| m.false_positives).collect();
+        assert_eq!(recalls, vec![1.0, 0.5, 0.0]);
+        assert_eq!(false_alarms, vec![1, 1, 0]);
+
+        let csv = curve_csv(&curve);
+        assert_eq!(csv.lines().count(), 4);
+        assert!(csv
+            .lines()
+            .nth(3)
+            .unwrap()
+            .starts_with("2,0,0,2,1.0000,0.0000,0.0000,"));
+    }
+}
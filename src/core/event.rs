@@ -1,25 +1,76 @@
-use anyhow::Result;
-use enigo::{Direction::Click, Key, Keyboard};
-use std::thread;
+use serde::{Serialize, Serializer};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Clone, Copy)]
+/// A match of one of the templates in the captured audio.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Detection {
+    /// Correlation at the peak of the match
+    pub score: f32,
+    /// Position in the processed audio at which the matched template starts
+    pub sample_offset: u64,
+    /// Position in the processed audio at which the match was decided on
+    pub sample_index: u64,
+    /// When the sample at `sample_index` was captured
+    #[serde(skip)]
+    pub captured_at: Instant,
+    /// `captured_at` as wall-clock time, serialized as seconds since the Unix epoch
+    #[serde(serialize_with = "serialize_unix_seconds")]
+    pub wall_clock: SystemTime,
+}
+
+fn serialize_unix_seconds<S: Serializer>(
+    time: &SystemTime,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64());
+    serializer.serialize_f64(seconds)
+}
+
+/// A timer of the fishing cycle running out without the expected sound being heard.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Timeout {
+    /// Milliseconds waited before giving up
+    pub waited_ms: u32,
+    /// When the timer ran out, serialized as seconds since the Unix epoch
+    #[serde(serialize_with = "serialize_unix_seconds")]
+    pub wall_clock: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(tag = "event")]
 pub enum FroskEvent {
-    FishBite { score: f32 },
+    FishBite(Detection),
+    CastSplash(Detection),
+    BobberLanded(Detection),
+    NoFishHooked(Detection),
+    InventoryFull(Detection),
+    /// No bite was heard before the cast expired.
+    CastTimedOut(Timeout),
 }
 
-pub fn handle_event(event: FroskEvent) -> Result<()> {
-    match event {
-        FroskEvent::FishBite { score: _ } => {
-            let mut enigo = enigo::Enigo::new(&enigo::Settings::default())?;
-            // reel it in
-            enigo.key(Key::F9, Click)?;
-            thread::sleep(std::time::Duration::from_millis(1000));
-            // just spam this a few times for now
-            for _ in 0..10 {
-                enigo.key(Key::F10, Click)?;
-                thread::sleep(std::time::Duration::from_millis(200));
-            }
-            Ok(())
+impl FroskEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FroskEvent::FishBite(_) => "FishBite",
+            FroskEvent::CastSplash(_) => "CastSplash",
+            FroskEvent::BobberLanded(_) => "BobberLanded",
+            FroskEvent::NoFishHooked(_) => "NoFishHooked",
+            FroskEvent::InventoryFull(_) => "InventoryFull",
+            FroskEvent::CastTimedOut(_) => "CastTimedOut",
+        }
+    }
+
+    /// The match behind the event, if it was detected in the audio.
+    pub fn detection(&self) -> Option<&Detection> {
+        match self {
+            FroskEvent::FishBite(detection)
+            | FroskEvent::CastSplash(detection)
+            | FroskEvent::BobberLanded(detection)
+            | FroskEvent::NoFishHooked(detection)
+            | FroskEvent::InventoryFull(detection) => Some(detection),
+            FroskEvent::CastTimedOut(_) => None,
         }
     }
 }
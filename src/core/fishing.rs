@@ -0,0 +1,632 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::{
+    event::{FroskEvent, Timeout},
+    focus::{FocusGuard, FocusPolicy},
+    input::{InputBackend, Key, KeyBinding},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FishingState {
+    /// Not fishing yet. A bite or a cast confirmation still gets picked up,
+    /// so a cast made by hand starts the cycle too.
+    Idle,
+    /// The cast key was pressed and the bobber hasn't been confirmed yet.
+    Casting,
+    WaitingForBite,
+    /// The bobber was clicked, waiting for the catch to reach the loot window.
+    Reeling,
+    Looting,
+    /// Pausing before the next cast.
+    Recasting,
+    /// Gave up, e.g. after repeated failed casts or a full inventory.
+    Failed,
+}
+
+/// A key press requested by the [`FishingCycle`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Cast,
+    Reel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FishingConfig {
+    /// Time for the bobber to land after casting. With `confirm_casts`, this is
+    /// how long to wait for a cast sound before casting again.
+    pub cast_ms: u32,
+    /// Whether casts are confirmed by a cast splash or bobber landed event, which
+    /// requires templates for those sounds. Unset, casts are confirmed whenever
+    /// such a template is configured.
+    pub confirm_casts: Option<bool>,
+    /// Casts in a row that may go unconfirmed before giving up.
+    pub max_cast_attempts: u32,
+    /// Time from the cast to wait for a bite before casting again, as the cast expires in game.
+    pub cast_timeout_ms: u32,
+    /// Time between clicking the bobber and the loot window opening.
+    pub reel_ms: u32,
+    /// Time for the catch to be looted.
+    pub loot_ms: u32,
+    /// Pause after looting before casting again.
+    pub recast_delay_ms: u32,
+    /// What to do with key presses while the game doesn't have focus.
+    pub focus: FocusPolicy,
+}
+
+impl Default for FishingConfig {
+    fn default() -> Self {
+        Self {
+            cast_ms: 3000,
+            confirm_casts: None,
+            max_cast_attempts: 3,
+            cast_timeout_ms: 30_000,
+            reel_ms: 1000,
+            loot_ms: 1000,
+            recast_delay_ms: 500,
+            focus: FocusPolicy::default(),
+        }
+    }
+}
+
+/// The fishing loop as a state machine driven by [`FroskEvent`]s and time.
+/// It only decides which [`Action`]s to take, performing them is up to the caller.
+pub struct FishingCycle {
+    config: FishingConfig,
+    state: FishingState,
+    entered_at: Instant,
+    /// When the line was last cast, from which the cast timeout runs
+    cast_at: Instant,
+    cast_attempts: u32,
+}
+
+impl FishingCycle {
+    pub fn new(config: FishingConfig, now: Instant) -> Self {
+        Self {
+            config,
+            state: FishingState::Idle,
+            entered_at: now,
+            cast_at: now,
+            cast_attempts: 0,
+        }
+    }
+
+    pub fn state(&self) -> FishingState {
+        self.state
+    }
+
+    /// Changes the timings, taking effect from the next event or tick on.
+    pub fn set_config(&mut self, config: FishingConfig) {
+        self.config = config;
+    }
+
+    /// Starts fishing from any state by casting, e.g. to recover from [`FishingState::Failed`].
+    pub fn start(&mut self, now: Instant) -> Option<Action> {
+        self.cast_attempts = 0;
+        self.cast(now)
+    }
+
+    pub fn on_event(&mut self, event: &FroskEvent, now: Instant) -> Option<Action> {
+        match (self.state, event) {
+            (FishingState::Failed, _) => None,
+            (_, FroskEvent::InventoryFull(_)) => {
+                self.enter(FishingState::Failed, now);
+                None
+            }
+            (
+                FishingState::Idle | FishingState::Casting | FishingState::WaitingForBite,
+                FroskEvent::FishBite(_),
+            ) => {
+                self.cast_attempts = 0;
+                self.enter(FishingState::Reeling, now);
+                Some(Action::Reel)
+            }
+            (
+                FishingState::Idle | FishingState::Casting,
+                FroskEvent::CastSplash(_) | FroskEvent::BobberLanded(_),
+            ) => {
+                // a cast made by hand is only noticed now
+                if self.state == FishingState::Idle {
+                    self.cast_at = now;
+                }
+                self.cast_attempts = 0;
+                self.enter(FishingState::WaitingForBite, now);
+                None
+            }
+            (FishingState::WaitingForBite, FroskEvent::CastTimedOut(_)) => self.cast(now),
+            (FishingState::Reeling, FroskEvent::NoFishHooked(_)) => {
+                self.enter(FishingState::Recasting, now);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the event for a timer that ran out, which should be passed on to
+    /// [`FishingCycle::on_event`] like any detected event.
+    pub fn check_timers(&self, now: Instant) -> Option<FroskEvent> {
+        let waited = now.saturating_duration_since(self.cast_at);
+        match self.state {
+            FishingState::WaitingForBite
+                if waited >= Duration::from_millis(self.config.cast_timeout_ms as u64) =>
+            {
+                Some(FroskEvent::CastTimedOut(Timeout {
+                    waited_ms: waited.as_millis() as u32,
+                    wall_clock: SystemTime::now(),
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    /// Advances the timers, to be called regularly.
+    pub fn on_tick(&mut self, now: Instant) -> Option<Action> {
+        let elapsed = now.saturating_duration_since(self.entered_at);
+        let after = |ms: u32| elapsed >= Duration::from_millis(ms as u64);
+        match self.state {
+            FishingState::Casting if after(self.config.cast_ms) => {
+                if self.config.confirm_casts == Some(true) {
+                    self.cast(now)
+                } else {
+                    self.cast_attempts = 0;
+                    self.enter(FishingState::WaitingForBite, now);
+                    None
+                }
+            }
+            FishingState::Reeling if after(self.config.reel_ms) => {
+                self.enter(FishingState::Looting, now);
+                None
+            }
+            FishingState::Looting if after(self.config.loot_ms) => {
+                self.enter(FishingState::Recasting, now);
+                None
+            }
+            FishingState::Recasting if after(self.config.recast_delay_ms) => self.cast(now),
+            _ => None,
+        }
+    }
+
+    fn cast(&mut self, now: Instant) -> Option<Action> {
+        if self.cast_attempts >= self.config.max_cast_attempts {
+            self.enter(FishingState::Failed, now);
+            return None;
+        }
+        self.cast_attempts += 1;
+        self.cast_at = now;
+        self.enter(FishingState::Casting, now);
+        Some(Action::Cast)
+    }
+
+    fn enter(&mut self, state: FishingState, now: Instant) {
+        self.state = state;
+        self.entered_at = now;
+    }
+}
+
+/// The in-game key bindings used for each [`Action`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    /// Interact with target, which clicks the bobber
+    pub reel: KeyBinding,
+    /// The action bar slot holding Fishing
+    pub cast: KeyBinding,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            reel: KeyBinding::new(Key::F9),
+            cast: KeyBinding::new(Key::F10),
+        }
+    }
+}
+
+/// Runs a [`FishingCycle`] by pressing the in-game keys for its actions,
+/// `F9` to interact with the bobber and `F10` to fish unless configured otherwise.
+pub struct Fisher<B: InputBackend> {
+    cycle: FishingCycle,
+    keys: KeyBindings,
+    input: B,
+    focus: Option<Box<dyn FocusGuard + Send>>,
+    /// Held back until the game has focus, with [`FocusPolicy::Queue`], along
+    /// with the state the cycle entered for it
+    queued: Option<(Action, FishingState)>,
+}
+
+impl<B: InputBackend> Fisher<B> {
+    pub fn new(config: FishingConfig, input: B) -> Self {
+        Self {
+            cycle: FishingCycle::new(config, Instant::now()),
+            keys: KeyBindings::default(),
+            input,
+            focus: None,
+            queued: None,
+        }
+    }
+
+    pub fn with_key_bindings(mut self, keys: KeyBindings) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// Checks with `focus` before pressing keys, following the configured [`FocusPolicy`].
+    /// Without a guard keys are always pressed.
+    pub fn with_focus_guard(mut self, focus: impl FocusGuard + Send + 'static) -> Self {
+        self.focus = Some(Box::new(focus));
+        self
+    }
+
+    pub fn set_key_bindings(&mut self, keys: KeyBindings) {
+        self.keys = keys;
+    }
+
+    pub fn set_config(&mut self, config: FishingConfig) {
+        self.cycle.set_config(config);
+    }
+
+    pub fn input(&mut self) -> &mut B {
+        &mut self.input
+    }
+
+    pub fn state(&self) -> FishingState {
+        self.cycle.state()
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        let action = self.cycle.start(Instant::now());
+        self.perform(action)
+    }
+
+    pub fn handle_event(&mut self, event: &FroskEvent) -> Result<()> {
+        let action = self.cycle.on_event(event, Instant::now());
+        self.perform(action)
+    }
+
+    /// Advances the timers, returning the event for a timer that ran out.
+    /// A queued key press is sent once the game has focus again, unless the
+    /// cycle has moved on in the meantime, e.g. because the bite expired.
+    pub fn tick(&mut self) -> Result<Option<FroskEvent>> {
+        let now = Instant::now();
+        if let Some(event) = self.cycle.check_timers(now) {
+            let action = self.cycle.on_event(&event, now);
+            self.perform(action)?;
+            return Ok(Some(event));
+        }
+        let action = self.cycle.on_tick(now);
+        let state = self.cycle.state();
+        let queued = self
+            .queued
+            .take()
+            .filter(|&(_, queued_for)| queued_for == state);
+        self.perform(action.or(queued.map(|(action, _)| action)))?;
+        Ok(None)
+    }
+
+    /// The key press waiting for the game to get focus.
+    pub fn queued(&self) -> Option<Action> {
+        self.queued.map(|(action, _)| action)
+    }
+
+    fn perform(&mut self, action: Option<Action>) -> Result<()> {
+        let Some(action) = action else {
+            return Ok(());
+        };
+        if !self.may_press(action)? {
+            return Ok(());
+        }
+        // a newer action supersedes the queued one
+        self.queued = None;
+        match action {
+            Action::Cast => self.keys.cast.press(&mut self.input),
+            Action::Reel => self.keys.reel.press(&mut self.input),
+        }
+    }
+
+    /// Whether the key for `action` would reach the game, refocusing or queueing
+    /// it as configured if not.
+    fn may_press(&mut self, action: Action) -> Result<bool> {
+        let Some(focus) = &mut self.focus else {
+            return Ok(true);
+        };
+        match self.cycle.config.focus {
+            FocusPolicy::Off => Ok(true),
+            _ if focus.is_focused()? => Ok(true),
+            FocusPolicy::Skip => Ok(false),
+            FocusPolicy::Queue => {
+                self.queued = Some((action, self.cycle.state()));
+                Ok(false)
+            }
+            FocusPolicy::Refocus => {
+                focus.focus()?;
+                focus.is_focused()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        event::Detection,
+        input::{InputAction, RecordingBackend},
+    };
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::SystemTime,
+    };
+
+    fn detection() -> Detection {
+        Detection {
+            score: 1.0,
+            sample_offset: 0,
+            sample_index: 0,
+            captured_at: Instant::now(),
+            wall_clock: SystemTime::now(),
+        }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_full_cycle() {
+        let t0 = Instant::now();
+        let mut cycle = FishingCycle::new(FishingConfig::default(), t0);
+
+        assert_eq!(cycle.start(t0), Some(Action::Cast));
+        assert_eq!(cycle.on_tick(t0 + ms(2999)), None);
+        assert_eq!(cycle.on_tick(t0 + ms(3000)), None);
+        assert_eq!(cycle.state(), FishingState::WaitingForBite);
+
+        let bite = FroskEvent::FishBite(detection());
+        assert_eq!(cycle.on_event(&bite, t0 + ms(10_000)), Some(Action::Reel));
+        assert_eq!(cycle.state(), FishingState::Reeling);
+        // a second detection of the same bite is ignored
+        assert_eq!(cycle.on_event(&bite, t0 + ms(10_100)), None);
+
+        cycle.on_tick(t0 + ms(11_000));
+        assert_eq!(cycle.state(), FishingState::Looting);
+        cycle.on_tick(t0 + ms(12_000));
+        assert_eq!(cycle.state(), FishingState::Recasting);
+        assert_eq!(cycle.on_tick(t0 + ms(12_500)), Some(Action::Cast));
+        assert_eq!(cycle.state(), FishingState::Casting);
+    }
+
+    #[test]
+    fn test_unconfirmed_casts_are_retried_then_give_up() {
+        let t0 = Instant::now();
+        let config = FishingConfig {
+            confirm_casts: Some(true),
+            max_cast_attempts: 2,
+            ..FishingConfig::default()
+        };
+        let mut cycle = FishingCycle::new(config, t0);
+
+        assert_eq!(cycle.start(t0), Some(Action::Cast));
+        assert_eq!(cycle.on_tick(t0 + ms(3000)), Some(Action::Cast));
+        assert_eq!(cycle.on_tick(t0 + ms(6000)), None);
+        assert_eq!(cycle.state(), FishingState::Failed);
+
+        // events don't revive a failed cycle, only starting over does
+        let bite = FroskEvent::FishBite(detection());
+        assert_eq!(cycle.on_event(&bite, t0 + ms(7000)), None);
+        assert_eq!(cycle.start(t0 + ms(8000)), Some(Action::Cast));
+        let splash = FroskEvent::CastSplash(detection());
+        cycle.on_event(&splash, t0 + ms(9000));
+        assert_eq!(cycle.state(), FishingState::WaitingForBite);
+    }
+
+    #[test]
+    fn test_cast_times_out_without_bite() {
+        let t0 = Instant::now();
+        let config = FishingConfig {
+            cast_timeout_ms: 20_000,
+            ..FishingConfig::default()
+        };
+        let mut cycle = FishingCycle::new(config, t0);
+
+        let splash = FroskEvent::CastSplash(detection());
+        cycle.on_event(&splash, t0);
+        assert_eq!(cycle.state(), FishingState::WaitingForBite);
+        assert!(cycle.check_timers(t0 + ms(19_999)).is_none());
+
+        let timeout = cycle.check_timers(t0 + ms(20_000)).unwrap();
+        assert!(matches!(
+            timeout,
+            FroskEvent::CastTimedOut(Timeout {
+                waited_ms: 20_000,
+                ..
+            })
+        ));
+        assert_eq!(
+            cycle.on_event(&timeout, t0 + ms(20_000)),
+            Some(Action::Cast)
+        );
+        assert_eq!(cycle.state(), FishingState::Casting);
+        assert!(cycle.check_timers(t0 + ms(40_000)).is_none());
+    }
+
+    #[test]
+    fn test_cast_timeout_runs_from_the_cast() {
+        let t0 = Instant::now();
+        let mut cycle = FishingCycle::new(FishingConfig::default(), t0);
+
+        assert_eq!(cycle.start(t0), Some(Action::Cast));
+        assert_eq!(cycle.on_tick(t0 + ms(3000)), None);
+        assert_eq!(cycle.state(), FishingState::WaitingForBite);
+        assert!(cycle.check_timers(t0 + ms(29_999)).is_none());
+        assert!(cycle.check_timers(t0 + ms(30_000)).is_some());
+    }
+
+    #[test]
+    fn test_missed_bite_recasts_and_full_inventory_stops() {
+        let t0 = Instant::now();
+        let mut cycle = FishingCycle::new(FishingConfig::default(), t0);
+
+        // a cast made by hand is picked up from idle
+        let bite = FroskEvent::FishBite(detection());
+        assert_eq!(cycle.on_event(&bite, t0), Some(Action::Reel));
+        cycle.on_event(&FroskEvent::NoFishHooked(detection()), t0 + ms(500));
+        assert_eq!(cycle.state(), FishingState::Recasting);
+        assert_eq!(cycle.on_tick(t0 + ms(1000)), Some(Action::Cast));
+
+        cycle.on_event(&FroskEvent::InventoryFull(detection()), t0 + ms(1500));
+        assert_eq!(cycle.state(), FishingState::Failed);
+        assert_eq!(cycle.on_tick(t0 + ms(60_000)), None);
+    }
+
+    #[test]
+    fn test_fisher_presses_keys_for_actions() {
+        let mut fisher = Fisher::new(FishingConfig::default(), RecordingBackend::new());
+
+        fisher.start().unwrap();
+        assert_eq!(fisher.input().take(), [InputAction::KeyClick(Key::F10)]);
+
+        // reported sounds without an action don't press anything
+        fisher
+            .handle_event(&FroskEvent::BobberLanded(detection()))
+            .unwrap();
+        assert_eq!(fisher.tick().unwrap().map(|event| event.name()), None);
+        assert!(fisher.input().take().is_empty());
+
+        fisher
+            .handle_event(&FroskEvent::FishBite(detection()))
+            .unwrap();
+        assert_eq!(fisher.input().take(), [InputAction::KeyClick(Key::F9)]);
+    }
+
+    #[test]
+    fn test_fisher_uses_configured_key_bindings() {
+        let keys = KeyBindings {
+            reel: "Ctrl+E".parse().unwrap(),
+            cast: "1".parse().unwrap(),
+        };
+        let mut fisher =
+            Fisher::new(FishingConfig::default(), RecordingBackend::new()).with_key_bindings(keys);
+
+        fisher.start().unwrap();
+        assert_eq!(
+            fisher.input().take(),
+            [InputAction::KeyClick(Key::Unicode('1'))]
+        );
+        fisher
+            .handle_event(&FroskEvent::FishBite(detection()))
+            .unwrap();
+        assert_eq!(
+            fisher.input().take(),
+            [
+                InputAction::KeyDown(Key::Control),
+                InputAction::KeyClick(Key::Unicode('e')),
+                InputAction::KeyUp(Key::Control),
+            ]
+        );
+    }
+
+    /// Focus that tests switch between the game and another window.
+    #[derive(Clone, Default)]
+    struct MockFocus {
+        focused: Arc<AtomicBool>,
+        /// Whether refocusing brings the game to the front
+        refocusable: bool,
+    }
+
+    impl FocusGuard for MockFocus {
+        fn is_focused(&mut self) -> Result<bool> {
+            Ok(self.focused.load(Ordering::Relaxed))
+        }
+
+        fn focus(&mut self) -> Result<()> {
+            if self.refocusable {
+                self.focused.store(true, Ordering::Relaxed);
+            }
+            Ok(())
+        }
+    }
+
+    fn focus_fisher(policy: FocusPolicy, focus: &MockFocus) -> Fisher<RecordingBackend> {
+        let config = FishingConfig {
+            focus: policy,
+            ..FishingConfig::default()
+        };
+        Fisher::new(config, RecordingBackend::new()).with_focus_guard(focus.clone())
+    }
+
+    #[test]
+    fn test_focus_policies() {
+        let bite = FroskEvent::FishBite(detection());
+
+        let skip = MockFocus::default();
+        let mut fisher = focus_fisher(FocusPolicy::Skip, &skip);
+        fisher.start().unwrap();
+        assert!(fisher.input().take().is_empty());
+        skip.focused.store(true, Ordering::Relaxed);
+        fisher.handle_event(&bite).unwrap();
+        assert_eq!(fisher.input().take(), [InputAction::KeyClick(Key::F9)]);
+
+        let queue = MockFocus::default();
+        let mut fisher = focus_fisher(FocusPolicy::Queue, &queue);
+        fisher.start().unwrap();
+        fisher.tick().unwrap();
+        assert_eq!(fisher.queued(), Some(Action::Cast));
+        assert!(fisher.input().take().is_empty());
+        // only the latest key press is kept
+        fisher.handle_event(&bite).unwrap();
+        assert_eq!(fisher.queued(), Some(Action::Reel));
+        queue.focused.store(true, Ordering::Relaxed);
+        fisher.tick().unwrap();
+        assert_eq!(fisher.input().take(), [InputAction::KeyClick(Key::F9)]);
+        assert_eq!(fisher.queued(), None);
+
+        let refocus = MockFocus {
+            refocusable: true,
+            ..MockFocus::default()
+        };
+        let mut fisher = focus_fisher(FocusPolicy::Refocus, &refocus);
+        fisher.start().unwrap();
+        assert!(refocus.focused.load(Ordering::Relaxed));
+        assert_eq!(fisher.input().take(), [InputAction::KeyClick(Key::F10)]);
+
+        let mut fisher = focus_fisher(FocusPolicy::Off, &MockFocus::default());
+        fisher.start().unwrap();
+        assert_eq!(fisher.input().take(), [InputAction::KeyClick(Key::F10)]);
+    }
+
+    #[test]
+    fn test_queued_reel_is_dropped_once_the_bite_expired() {
+        let config = FishingConfig {
+            focus: FocusPolicy::Queue,
+            cast_timeout_ms: 20,
+            reel_ms: 10,
+            loot_ms: 60_000,
+            ..FishingConfig::default()
+        };
+        let focus = MockFocus::default();
+        let mut fisher =
+            Fisher::new(config, RecordingBackend::new()).with_focus_guard(focus.clone());
+
+        fisher
+            .handle_event(&FroskEvent::BobberLanded(detection()))
+            .unwrap();
+        fisher
+            .handle_event(&FroskEvent::FishBite(detection()))
+            .unwrap();
+        assert_eq!(fisher.queued(), Some(Action::Reel));
+
+        thread::sleep(ms(40));
+        fisher.tick().unwrap();
+        assert_eq!(fisher.state(), FishingState::Looting);
+        assert_eq!(fisher.queued(), None);
+        focus.focused.store(true, Ordering::Relaxed);
+        fisher.tick().unwrap();
+        assert!(fisher.input().take().is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::process::{process_locator, ProcessError, ProcessLocator, ProcessQuery};
+
+/// What to do with a key press while the game doesn't have focus.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FocusPolicy {
+    /// Press keys without checking which window has focus.
+    Off,
+    /// Drop the key press.
+    Skip,
+    /// Hold on to the latest key press until the game has focus again.
+    #[default]
+    Queue,
+    /// Bring the game to the front, then press the key, dropping it if the
+    /// game still doesn't have focus.
+    Refocus,
+}
+
+/// Checks that key presses would reach the game.
+pub trait FocusGuard {
+    /// Whether the game's window has keyboard focus.
+    fn is_focused(&mut self) -> Result<bool>;
+
+    /// Brings the game's window to the front.
+    fn focus(&mut self) -> Result<()>;
+}
+
+/// Compares the process owning the foreground window with the game processes,
+/// which are looked up on every check so a restarted game is still found.
+#[derive(Debug, Clone)]
+pub struct GameFocus {
+    process: ProcessQuery,
+}
+
+impl GameFocus {
+    /// Fails if the foreground window can't be found out here, e.g. without `xdotool`.
+    pub fn new(process: ProcessQuery) -> Result<Self> {
+        foreground_pid()?;
+        Ok(Self { process })
+    }
+}
+
+impl FocusGuard for GameFocus {
+    /// A game that isn't running doesn't have focus.
+    fn is_focused(&mut self) -> Result<bool> {
+        let Some(foreground) = foreground_pid()? else {
+            return Ok(false);
+        };
+        // the window may belong to any of them, e.g. to a helper under Wine
+        match process_locator().find_all(&self.process) {
+            Ok(games) => Ok(games.iter().any(|game| game.pid == foreground)),
+            Err(ProcessError::NotFound(_)) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Activates the window of the first game process that has one.
+    fn focus(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for game in process_locator().find_all(&self.process)? {
+            result = activate(game.pid);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn foreground_pid() -> Result<Option<u32>> {
+    use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId};
+
+    let mut pid = 0;
+    unsafe {
+        let window = GetForegroundWindow();
+        if window.0 == 0 || GetWindowThreadProcessId(window, Some(&mut pid)) == 0 {
+            return Ok(None);
+        }
+    }
+    Ok(Some(pid))
+}
+
+#[cfg(target_os = "windows")]
+fn activate(pid: u32) -> Result<()> {
+    use super::process::windows::windows;
+    use anyhow::bail;
+    use windows::Win32::UI::WindowsAndMessaging::SetForegroundWindow;
+
+    let window = windows()?
+        .into_iter()
+        .find(|window| window.process_id == pid && window.is_main())
+        .with_context(|| format!("process {pid} has no visible window"))?;
+    if !unsafe { SetForegroundWindow(window.window) }.as_bool() {
+        bail!("Windows refused to bring {:?} to the front", window.title);
+    }
+    Ok(())
+}
+
+/// Asks `xdotool`, which works on X11 and with Wine under XWayland.
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn foreground_pid() -> Result<Option<u32>> {
+    let output = run("xdotool", &["getactivewindow", "getwindowpid"])?;
+    // no focused window, or one without a pid like some desktop shells, is never the game
+    Ok(output.and_then(|output| output.trim().parse().ok()))
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn activate(pid: u32) -> Result<()> {
+    run(
+        "xdotool",
+        &[
+            "search",
+            "--onlyvisible",
+            "--pid",
+            &pid.to_string(),
+            "windowactivate",
+        ],
+    )?
+    .with_context(|| format!("xdotool found no window of process {pid}"))?;
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn foreground_pid() -> Result<Option<u32>> {
+    let output = run(
+        "osascript",
+        &[
+            "-e",
+            "tell application \"System Events\" to unix id of first process whose frontmost is true",
+        ],
+    )?;
+    Ok(output.and_then(|output| output.trim().parse().ok()))
+}
+
+#[cfg(target_os = "macos")]
+fn activate(pid: u32) -> Result<()> {
+    run(
+        "osascript",
+        &[
+            "-e",
+            &format!(
+                "tell application \"System Events\" to set frontmost of first process whose unix id is {pid} to true"
+            ),
+        ],
+    )?
+    .context("System Events could not bring the game to the front")?;
+    Ok(())
+}
+
+/// Runs `program`, returning its output if it succeeded.
+#[cfg(not(target_os = "windows"))]
+fn run(program: &str, args: &[&str]) -> Result<Option<String>> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("failed to run {program}, is it installed?"))?;
+    Ok(output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned()))
+}
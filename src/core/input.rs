@@ -0,0 +1,339 @@
+use anyhow::{anyhow, bail, Result};
+use enigo::{
+    Coordinate,
+    Direction::{Click, Press, Release},
+    Keyboard, Mouse,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr, thread, time::Duration};
+
+pub use enigo::{Button, Key};
+
+/// Simulated keyboard and mouse input sent to the game.
+pub trait InputBackend {
+    fn key_down(&mut self, key: Key) -> Result<()>;
+
+    fn key_up(&mut self, key: Key) -> Result<()>;
+
+    fn key_click(&mut self, key: Key) -> Result<()> {
+        self.key_down(key)?;
+        self.key_up(key)
+    }
+
+    /// Moves the mouse to absolute screen coordinates.
+    fn mouse_move(&mut self, x: i32, y: i32) -> Result<()>;
+
+    fn mouse_click(&mut self, button: Button) -> Result<()>;
+
+    fn delay(&mut self, duration: Duration) -> Result<()> {
+        thread::sleep(duration);
+        Ok(())
+    }
+}
+
+const FUNCTION_KEYS: [Key; 20] = [
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+    Key::F13,
+    Key::F14,
+    Key::F15,
+    Key::F16,
+    Key::F17,
+    Key::F18,
+    Key::F19,
+    Key::F20,
+];
+
+const NAMED_KEYS: [(&str, Key); 5] = [
+    ("Space", Key::Space),
+    ("Enter", Key::Return),
+    ("Tab", Key::Tab),
+    ("Escape", Key::Escape),
+    ("Backspace", Key::Backspace),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub meta: bool,
+}
+
+impl Modifiers {
+    fn keys(&self) -> impl Iterator<Item = Key> {
+        [
+            (self.ctrl, Key::Control),
+            (self.alt, Key::Alt),
+            (self.shift, Key::Shift),
+            (self.meta, Key::Meta),
+        ]
+        .into_iter()
+        .filter_map(|(held, key)| held.then_some(key))
+    }
+}
+
+/// A key pressed while holding any number of modifiers, written like `Shift+F10`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBinding {
+    pub modifiers: Modifiers,
+    pub key: Key,
+}
+
+impl KeyBinding {
+    pub const fn new(key: Key) -> Self {
+        Self {
+            modifiers: Modifiers {
+                ctrl: false,
+                alt: false,
+                shift: false,
+                meta: false,
+            },
+            key,
+        }
+    }
+
+    /// Clicks the key, holding down the modifiers around it.
+    pub fn press(&self, input: &mut (impl InputBackend + ?Sized)) -> Result<()> {
+        for modifier in self.modifiers.keys() {
+            input.key_down(modifier)?;
+        }
+        input.key_click(self.key)?;
+        let modifiers: Vec<Key> = self.modifiers.keys().collect();
+        for &modifier in modifiers.iter().rev() {
+            input.key_up(modifier)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [
+            (self.modifiers.ctrl, "Ctrl"),
+            (self.modifiers.alt, "Alt"),
+            (self.modifiers.shift, "Shift"),
+            (self.modifiers.meta, "Meta"),
+        ] {
+            if held {
+                write!(f, "{name}+")?;
+            }
+        }
+        if let Some(i) = FUNCTION_KEYS.iter().position(|&key| key == self.key) {
+            return write!(f, "F{}", i + 1);
+        }
+        if let Some((name, _)) = NAMED_KEYS.iter().find(|(_, key)| *key == self.key) {
+            return f.write_str(name);
+        }
+        match self.key {
+            Key::Unicode(c) => write!(f, "{}", c.to_uppercase()),
+            key => write!(f, "{key:?}"),
+        }
+    }
+}
+
+impl FromStr for KeyBinding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or_default();
+        let mut modifiers = Modifiers::default();
+        for modifier in parts {
+            let held = match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => &mut modifiers.ctrl,
+                "alt" | "option" => &mut modifiers.alt,
+                "shift" => &mut modifiers.shift,
+                "meta" | "cmd" | "super" | "win" => &mut modifiers.meta,
+                _ => bail!("unknown modifier {modifier:?} in key binding {s:?}"),
+            };
+            *held = true;
+        }
+        Ok(Self {
+            modifiers,
+            key: parse_key(key)
+                .ok_or_else(|| anyhow!("unknown key {key:?} in key binding {s:?}"))?,
+        })
+    }
+}
+
+impl Serialize for KeyBinding {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyBinding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return (!c.is_whitespace()).then(|| Key::Unicode(c.to_ascii_lowercase()));
+    }
+    if let Some(n) = name
+        .strip_prefix(['F', 'f'])
+        .and_then(|n| n.parse::<usize>().ok())
+    {
+        return FUNCTION_KEYS.get(n.checked_sub(1)?).copied();
+    }
+    match name.to_ascii_lowercase().as_str() {
+        "return" => Some(Key::Return),
+        "esc" => Some(Key::Escape),
+        name => NAMED_KEYS
+            .iter()
+            .find(|(key_name, _)| key_name.to_ascii_lowercase() == name)
+            .map(|&(_, key)| key),
+    }
+}
+
+/// Sends input to the system through enigo.
+pub struct EnigoBackend {
+    enigo: enigo::Enigo,
+}
+
+impl EnigoBackend {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            enigo: enigo::Enigo::new(&enigo::Settings::default())?,
+        })
+    }
+}
+
+impl InputBackend for EnigoBackend {
+    fn key_down(&mut self, key: Key) -> Result<()> {
+        Ok(self.enigo.key(key, Press)?)
+    }
+
+    fn key_up(&mut self, key: Key) -> Result<()> {
+        Ok(self.enigo.key(key, Release)?)
+    }
+
+    fn key_click(&mut self, key: Key) -> Result<()> {
+        Ok(self.enigo.key(key, Click)?)
+    }
+
+    fn mouse_move(&mut self, x: i32, y: i32) -> Result<()> {
+        Ok(self.enigo.move_mouse(x, y, Coordinate::Abs)?)
+    }
+
+    fn mouse_click(&mut self, button: Button) -> Result<()> {
+        Ok(self.enigo.button(button, Click)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputAction {
+    KeyDown(Key),
+    KeyUp(Key),
+    KeyClick(Key),
+    MouseMove { x: i32, y: i32 },
+    MouseClick(Button),
+    Delay(Duration),
+}
+
+/// Records the input instead of sending it, e.g. to test the fishing logic
+/// without a display server. Delays are recorded without sleeping.
+#[derive(Debug, Default)]
+pub struct RecordingBackend {
+    pub actions: Vec<InputAction>,
+}
+
+impl RecordingBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the recorded actions, leaving the recording empty.
+    pub fn take(&mut self) -> Vec<InputAction> {
+        std::mem::take(&mut self.actions)
+    }
+}
+
+impl InputBackend for RecordingBackend {
+    fn key_down(&mut self, key: Key) -> Result<()> {
+        self.actions.push(InputAction::KeyDown(key));
+        Ok(())
+    }
+
+    fn key_up(&mut self, key: Key) -> Result<()> {
+        self.actions.push(InputAction::KeyUp(key));
+        Ok(())
+    }
+
+    fn key_click(&mut self, key: Key) -> Result<()> {
+        self.actions.push(InputAction::KeyClick(key));
+        Ok(())
+    }
+
+    fn mouse_move(&mut self, x: i32, y: i32) -> Result<()> {
+        self.actions.push(InputAction::MouseMove { x, y });
+        Ok(())
+    }
+
+    fn mouse_click(&mut self, button: Button) -> Result<()> {
+        self.actions.push(InputAction::MouseClick(button));
+        Ok(())
+    }
+
+    fn delay(&mut self, duration: Duration) -> Result<()> {
+        self.actions.push(InputAction::Delay(duration));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_binding_round_trips_through_string() {
+        let binding: KeyBinding = "shift+ctrl+f10".parse().unwrap();
+        assert_eq!(binding.key, Key::F10);
+        assert!(binding.modifiers.shift && binding.modifiers.ctrl);
+        assert!(!binding.modifiers.alt && !binding.modifiers.meta);
+        assert_eq!(binding.to_string(), "Ctrl+Shift+F10");
+
+        for s in ["F9", "Alt+Space", "Q", "Meta+Enter", "1"] {
+            assert_eq!(s.parse::<KeyBinding>().unwrap().to_string(), s);
+        }
+        for s in ["", "F0", "F21", "Hyper+F1", "Shift+", "Foo"] {
+            assert!(s.parse::<KeyBinding>().is_err(), "{s:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn test_key_binding_holds_modifiers_around_key() {
+        let mut input = RecordingBackend::new();
+        "Alt+Shift+F10"
+            .parse::<KeyBinding>()
+            .unwrap()
+            .press(&mut input)
+            .unwrap();
+        assert_eq!(
+            input.take(),
+            [
+                InputAction::KeyDown(Key::Alt),
+                InputAction::KeyDown(Key::Shift),
+                InputAction::KeyClick(Key::F10),
+                InputAction::KeyUp(Key::Shift),
+                InputAction::KeyUp(Key::Alt),
+            ]
+        );
+    }
+}
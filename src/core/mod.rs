@@ -1,4 +1,10 @@
 pub mod capture;
+pub mod config;
 pub mod dsp;
+pub mod eval;
 pub mod event;
+pub mod fishing;
+pub mod focus;
+pub mod input;
 pub mod process;
+pub mod recorder;
@@ -1,17 +1,354 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, io};
+use thiserror::Error;
+
+/// A running process as seen by a [`ProcessLocator`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    /// Name the system knows the process by, e.g. `Wow.exe`
+    pub name: String,
+    /// Path of the executable, if it can be read
+    pub executable: Option<String>,
+    /// Command line the process was started with, if it can be read
+    pub command: Vec<String>,
+    /// Titles of the process's visible windows
+    pub window_titles: Vec<String>,
+}
+
+/// Picks out the game process, written in the config as `{ executable = "Wow.exe" }`
+/// or `{ window-title = "World of Warcraft" }`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProcessQuery {
+    /// Executable file name, compared case-insensitively. Under Wine this is the
+    /// Windows executable rather than the Wine loader.
+    Executable(String),
+    /// Exact title of one of the process's windows
+    WindowTitle(String),
+}
+
+impl Default for ProcessQuery {
+    /// World of Warcraft as it runs on this platform.
+    fn default() -> Self {
+        if cfg!(target_os = "windows") {
+            ProcessQuery::WindowTitle("World of Warcraft".to_string())
+        } else if cfg!(target_os = "macos") {
+            ProcessQuery::Executable("World of Warcraft".to_string())
+        } else {
+            // under Wine
+            ProcessQuery::Executable("Wow.exe".to_string())
+        }
+    }
+}
+
+impl fmt::Display for ProcessQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessQuery::Executable(name) => write!(f, "executable {name:?}"),
+            ProcessQuery::WindowTitle(title) => write!(f, "window {title:?}"),
+        }
+    }
+}
+
+/// Last component of a Unix or Windows path.
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+impl ProcessQuery {
+    pub fn matches(&self, process: &ProcessInfo) -> bool {
+        match self {
+            ProcessQuery::Executable(name) => [
+                Some(process.name.as_str()),
+                process.executable.as_deref(),
+                process.command.first().map(String::as_str),
+            ]
+            .into_iter()
+            .flatten()
+            .any(|path| file_name(path).eq_ignore_ascii_case(name)),
+            ProcessQuery::WindowTitle(title) => process.window_titles.contains(title),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ProcessError {
+    #[error("no process matches {0}")]
+    NotFound(ProcessQuery),
+    #[error("finding a process by {0} is not supported on this platform")]
+    Unsupported(ProcessQuery),
+    #[error("listing processes failed: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Finds running processes, so capture and input can be bound to the game.
+pub trait ProcessLocator {
+    fn processes(&self) -> Result<Vec<ProcessInfo>, ProcessError>;
+
+    /// Every process matching `query` ordered by pid, as the game may run
+    /// several, e.g. helpers under Wine or a launcher. Fails if there are none.
+    fn find_all(&self, query: &ProcessQuery) -> Result<Vec<ProcessInfo>, ProcessError> {
+        find_processes(self.processes()?, query)
+    }
+
+    /// The process matching `query` with the lowest pid, which is usually the
+    /// parent when the game runs several.
+    fn find(&self, query: &ProcessQuery) -> Result<ProcessInfo, ProcessError> {
+        Ok(self.find_all(query)?.remove(0))
+    }
+}
+
+fn find_processes(
+    processes: Vec<ProcessInfo>,
+    query: &ProcessQuery,
+) -> Result<Vec<ProcessInfo>, ProcessError> {
+    let mut matching: Vec<ProcessInfo> = processes
+        .into_iter()
+        .filter(|process| query.matches(process))
+        .collect();
+    if matching.is_empty() {
+        return Err(ProcessError::NotFound(query.clone()));
+    }
+    matching.sort_by_key(|process| process.pid);
+    Ok(matching)
+}
+
+/// The locator for the platform frosk is running on.
+pub fn process_locator() -> impl ProcessLocator {
+    #[cfg(target_os = "windows")]
+    {
+        windows::WindowsLocator
+    }
+    #[cfg(target_os = "macos")]
+    {
+        ps::PsLocator
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    {
+        procfs::ProcLocator::default()
+    }
+}
+
+/// Reads processes from `/proc` on Linux, where there are no window titles to match.
+pub mod procfs {
+    use super::{ProcessError, ProcessInfo, ProcessLocator, ProcessQuery};
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    #[derive(Debug, Clone)]
+    pub struct ProcLocator {
+        root: PathBuf,
+    }
+
+    impl Default for ProcLocator {
+        fn default() -> Self {
+            Self::new("/proc")
+        }
+    }
+
+    impl ProcLocator {
+        /// Reads the procfs mounted at `root`, which tests point at a fake tree.
+        pub fn new(root: impl Into<PathBuf>) -> Self {
+            Self { root: root.into() }
+        }
+    }
+
+    /// Reads what there is to read about a process, or `None` if it has exited.
+    pub fn read_process(dir: &Path, pid: u32) -> Option<ProcessInfo> {
+        let name = fs::read_to_string(dir.join("comm")).ok()?;
+        // other users' processes hide their executable, and kernel threads have no command line
+        let executable = fs::read_link(dir.join("exe"))
+            .ok()
+            .map(|path| path.to_string_lossy().into_owned());
+        let command = fs::read(dir.join("cmdline"))
+            .map(|bytes| {
+                bytes
+                    .split(|&b| b == 0)
+                    .filter(|arg| !arg.is_empty())
+                    .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(ProcessInfo {
+            pid,
+            name: name.trim_end_matches('\n').to_string(),
+            executable,
+            command,
+            window_titles: Vec::new(),
+        })
+    }
+
+    impl ProcessLocator for ProcLocator {
+        fn processes(&self) -> Result<Vec<ProcessInfo>, ProcessError> {
+            let mut processes = Vec::new();
+            for entry in fs::read_dir(&self.root)? {
+                let entry = entry?;
+                let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse().ok()) else {
+                    continue;
+                };
+                processes.extend(read_process(&entry.path(), pid));
+            }
+            Ok(processes)
+        }
+
+        fn find_all(&self, query: &ProcessQuery) -> Result<Vec<ProcessInfo>, ProcessError> {
+            if let ProcessQuery::WindowTitle(_) = query {
+                return Err(ProcessError::Unsupported(query.clone()));
+            }
+            super::find_processes(self.processes()?, query)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn fake_process(root: &Path, pid: &str, comm: &str, cmdline: &[&str]) {
+            let dir = root.join(pid);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("comm"), format!("{comm}\n")).unwrap();
+            let mut bytes = Vec::new();
+            for arg in cmdline {
+                bytes.extend_from_slice(arg.as_bytes());
+                bytes.push(0);
+            }
+            fs::write(dir.join("cmdline"), bytes).unwrap();
+        }
+
+        #[test]
+        fn test_finds_wine_process_in_fake_procfs() {
+            let root = std::env::temp_dir().join(format!("frosk-proc-{}", std::process::id()));
+            fake_process(&root, "1", "systemd", &["/sbin/init"]);
+            fake_process(&root, "812", "wineserver", &["/usr/bin/wineserver"]);
+            fake_process(
+                &root,
+                "900",
+                "wine64-preload",
+                &[
+                    "C:\\Program Files\\World of Warcraft\\_retail_\\Wow.exe",
+                    "-launch",
+                ],
+            );
+            fake_process(&root, "901", "Wow.exe", &[]);
+            fs::create_dir_all(root.join("self")).unwrap();
+            fs::write(root.join("uptime"), "1.0 1.0\n").unwrap();
+
+            let locator = ProcLocator::new(&root);
+            let processes = locator.processes().unwrap();
+            assert_eq!(processes.len(), 4);
+            let wow = locator
+                .find(&ProcessQuery::Executable("wow.exe".to_string()))
+                .unwrap();
+            assert_eq!(wow.pid, 900);
+            assert_eq!(wow.command.len(), 2);
+            assert_eq!(wow.name, "wine64-preload");
+            let all: Vec<u32> = locator
+                .find_all(&ProcessQuery::Executable("wow.exe".to_string()))
+                .unwrap()
+                .iter()
+                .map(|process| process.pid)
+                .collect();
+            assert_eq!(all, vec![900, 901]);
+
+            assert!(matches!(
+                locator.find(&ProcessQuery::Executable("Wow".to_string())),
+                Err(ProcessError::NotFound(_))
+            ));
+            assert!(matches!(
+                locator.find(&ProcessQuery::WindowTitle("World of Warcraft".to_string())),
+                Err(ProcessError::Unsupported(_))
+            ));
+            fs::remove_dir_all(&root).unwrap();
+        }
+    }
+}
+
+/// Lists processes with `ps` on macOS.
+pub mod ps {
+    use super::{ProcessError, ProcessInfo, ProcessLocator};
+    use std::process::Command;
+
+    /// Parses the output of `ps -Ao pid=,comm=`.
+    pub fn parse_ps(output: &str) -> Vec<ProcessInfo> {
+        output
+            .lines()
+            .filter_map(|line| {
+                let (pid, executable) = line.trim().split_once(char::is_whitespace)?;
+                let executable = executable.trim();
+                Some(ProcessInfo {
+                    pid: pid.parse().ok()?,
+                    name: super::file_name(executable).to_string(),
+                    executable: Some(executable.to_string()),
+                    ..ProcessInfo::default()
+                })
+            })
+            .collect()
+    }
+
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct PsLocator;
+
+    impl ProcessLocator for PsLocator {
+        fn processes(&self) -> Result<Vec<ProcessInfo>, ProcessError> {
+            let output = Command::new("ps").args(["-Ao", "pid=,comm="]).output()?;
+            Ok(parse_ps(&String::from_utf8_lossy(&output.stdout)))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::core::process::ProcessQuery;
+
+        #[test]
+        fn test_parse_ps() {
+            let processes = parse_ps(
+                "    1 /sbin/launchd\n  4242 /Applications/World of Warcraft/_retail_/World of Warcraft.app/Contents/MacOS/World of Warcraft\n",
+            );
+            assert_eq!(processes.len(), 2);
+            assert_eq!(processes[1].pid, 4242);
+            assert_eq!(processes[1].name, "World of Warcraft");
+            assert!(
+                ProcessQuery::Executable("world of warcraft".to_string()).matches(&processes[1])
+            );
+        }
+    }
+}
+
 #[cfg(target_family = "windows")]
 pub mod windows {
     use std::sync::{Arc, Mutex};
     use thiserror::Error;
     use windows::Win32::{
         Foundation::*,
-        UI::WindowsAndMessaging::{EnumWindows, GetWindowTextW, GetWindowThreadProcessId},
+        System::Diagnostics::ToolHelp::{
+            CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+            TH32CS_SNAPPROCESS,
+        },
+        UI::WindowsAndMessaging::{
+            EnumWindows, GetWindowTextW, GetWindowThreadProcessId, IsWindowVisible,
+        },
     };
 
+    use super::{ProcessError, ProcessInfo, ProcessLocator};
+
     #[derive(Debug, Clone)]
     pub struct WindowInfo {
         pub window: HWND,
         pub title: String,
         pub process_id: u32,
+        /// Hidden windows include the IME windows every GUI process has
+        pub visible: bool,
+    }
+
+    impl WindowInfo {
+        /// A window the user can see and switch to, rather than a helper window.
+        pub fn is_main(&self) -> bool {
+            self.visible && !self.title.is_empty()
+        }
     }
 
     #[derive(Debug, Error)]
@@ -23,6 +360,14 @@ pub mod windows {
     }
 
     pub fn get_window_info(title: &str) -> Result<WindowInfo, GetWindowError> {
+        windows()?
+            .into_iter()
+            .find(|i| i.title == title)
+            .ok_or(GetWindowError::GameNotFound)
+    }
+
+    /// All top-level windows.
+    pub fn windows() -> Result<Vec<WindowInfo>, GetWindowError> {
         let window_infos = Arc::new(Mutex::new(Vec::<WindowInfo>::new()));
 
         let window_infos_clone = Arc::clone(&window_infos);
@@ -35,13 +380,7 @@ pub mod windows {
         }
 
         let infos = window_infos.lock().unwrap();
-        let window = infos
-            .iter()
-            .filter(|i| i.title == title)
-            .next()
-            .ok_or(GetWindowError::GameNotFound)?;
-
-        Ok(window.clone())
+        Ok(infos.clone())
     }
 
     pub extern "system" fn enum_callback(window: HWND, lparam: LPARAM) -> BOOL {
@@ -66,10 +405,69 @@ pub mod windows {
                 window,
                 title,
                 process_id,
+                visible: IsWindowVisible(window).as_bool(),
             };
             vec.push(info);
 
             true.into()
         }
     }
+
+    /// Lists processes with a Toolhelp snapshot, along with the titles of their visible windows.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct WindowsLocator;
+
+    impl ProcessLocator for WindowsLocator {
+        fn processes(&self) -> Result<Vec<ProcessInfo>, ProcessError> {
+            let windows = windows().map_err(|err| ProcessError::Io(std::io::Error::other(err)))?;
+            let mut processes = Vec::new();
+            unsafe {
+                let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)
+                    .map_err(|err| ProcessError::Io(std::io::Error::other(err)))?;
+                let mut entry = PROCESSENTRY32W {
+                    dwSize: size_of::<PROCESSENTRY32W>() as u32,
+                    ..Default::default()
+                };
+                let mut next = Process32FirstW(snapshot, &mut entry);
+                while next.is_ok() {
+                    let len = entry
+                        .szExeFile
+                        .iter()
+                        .position(|&c| c == 0)
+                        .unwrap_or(entry.szExeFile.len());
+                    let pid = entry.th32ProcessID;
+                    processes.push(ProcessInfo {
+                        pid,
+                        name: String::from_utf16_lossy(&entry.szExeFile[..len]),
+                        window_titles: windows
+                            .iter()
+                            .filter(|window| window.process_id == pid && window.is_main())
+                            .map(|window| window.title.clone())
+                            .collect(),
+                        ..ProcessInfo::default()
+                    });
+                    next = Process32NextW(snapshot, &mut entry);
+                }
+                let _ = CloseHandle(snapshot);
+            }
+            Ok(processes)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_title_matches_any_window() {
+        let game = ProcessInfo {
+            pid: 4242,
+            name: "Wow.exe".to_string(),
+            window_titles: vec!["Battle.net".to_string(), "World of Warcraft".to_string()],
+            ..ProcessInfo::default()
+        };
+        assert!(ProcessQuery::WindowTitle("World of Warcraft".to_string()).matches(&game));
+        assert!(!ProcessQuery::WindowTitle("World".to_string()).matches(&game));
+    }
 }
@@ -0,0 +1,401 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::BufWriter,
+    path::PathBuf,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::event::FroskEvent;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecordMode {
+    /// Everything that is captured, split into files of `segment_secs`.
+    #[default]
+    Continuous,
+    /// Only the audio around each event, one file per event.
+    Clips,
+}
+
+/// Settings for recording the captured audio, to find out what was heard when
+/// a bite was missed or detected when there was none.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecorderConfig {
+    pub enabled: bool,
+    /// Folder the recordings are written to, relative to the config file
+    pub dir: PathBuf,
+    pub mode: RecordMode,
+    /// Length of each file in continuous mode
+    pub segment_secs: u32,
+    /// Audio kept before each event in clips mode
+    pub before_ms: u32,
+    /// Audio kept after each event in clips mode
+    pub after_ms: u32,
+    /// Files written by a session beyond which the oldest is deleted, 0 to keep them all
+    pub max_files: usize,
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: dirs::data_dir()
+                .map(|dir| dir.join("frosk").join("recordings"))
+                .unwrap_or_else(|| PathBuf::from("recordings")),
+            mode: RecordMode::default(),
+            segment_secs: 60,
+            before_ms: 5000,
+            after_ms: 2000,
+            max_files: 10,
+        }
+    }
+}
+
+type WavWriter = hound::WavWriter<BufWriter<File>>;
+
+/// Tees captured chunks into WAV files, which can be replayed with the CLI's `--file`.
+/// Writing blocks on the disk, so use [`Recorder::spawn`] from a capture callback.
+pub struct Recorder {
+    config: RecorderConfig,
+    sample_rate: u32,
+    writer: Option<WavWriter>,
+    /// Samples left to write to the current file
+    remaining: u64,
+    /// The most recent audio, written at the start of a clip
+    history: VecDeque<f32>,
+    /// Files written so far, oldest first
+    files: VecDeque<PathBuf>,
+}
+
+impl Recorder {
+    /// Records mono audio at `sample_rate` into `config.dir`, creating it if needed.
+    pub fn new(config: RecorderConfig, sample_rate: u32) -> Result<Self> {
+        fs::create_dir_all(&config.dir)
+            .with_context(|| format!("creating {}", config.dir.display()))?;
+        Ok(Self {
+            config,
+            sample_rate,
+            writer: None,
+            remaining: 0,
+            history: VecDeque::new(),
+            files: VecDeque::new(),
+        })
+    }
+
+    /// Moves the recorder to a thread of its own, returning a handle that passes
+    /// it chunks and events without waiting for them to be written.
+    pub fn spawn(mut self) -> Result<RecorderHandle> {
+        let (commands_tx, commands_rx) = mpsc::channel::<Command>();
+        let (errors_tx, errors_rx) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("frosk-recorder".into())
+            .spawn(move || {
+                for command in commands_rx {
+                    let result = match command {
+                        Command::Push(chunk) => self.push(&chunk),
+                        Command::Mark(event) => self.mark(&event),
+                    };
+                    // later chunks would only fail the same way, e.g. on a full disk
+                    if let Err(err) = result {
+                        let _ = errors_tx.send(err);
+                        return;
+                    }
+                }
+                if let Err(err) = self.finish() {
+                    let _ = errors_tx.send(err);
+                }
+            })?;
+        Ok(RecorderHandle {
+            commands: Some(commands_tx),
+            errors: errors_rx,
+            thread: Some(thread),
+        })
+    }
+
+    /// Files written so far that haven't been deleted, oldest first.
+    pub fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.files.iter()
+    }
+
+    /// Records a chunk as passed to the capture callback.
+    pub fn push(&mut self, chunk: &[f32]) -> Result<()> {
+        match self.config.mode {
+            RecordMode::Continuous => {
+                let segment = self.samples(self.config.segment_secs as u64 * 1000).max(1);
+                let mut chunk = chunk;
+                while !chunk.is_empty() {
+                    if self.writer.is_none() {
+                        self.open("session")?;
+                        self.remaining = segment;
+                    }
+                    let (now, later) = chunk.split_at(chunk.len().min(self.remaining as usize));
+                    self.write(now)?;
+                    chunk = later;
+                }
+            }
+            RecordMode::Clips => {
+                if self.writer.is_some() {
+                    let len = chunk.len().min(self.remaining as usize);
+                    self.write(&chunk[..len])?;
+                }
+                let capacity = self.samples(self.config.before_ms as u64) as usize;
+                self.history.extend(chunk);
+                let excess = self.history.len().saturating_sub(capacity);
+                self.history.drain(..excess);
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts a clip for `event` in clips mode, or extends the clip being written.
+    /// The event is expected to be detected in audio that was already pushed.
+    pub fn mark(&mut self, event: &FroskEvent) -> Result<()> {
+        if self.config.mode != RecordMode::Clips {
+            return Ok(());
+        }
+        let after = self.samples(self.config.after_ms as u64);
+        if self.writer.is_some() {
+            self.remaining = self.remaining.max(after);
+            return Ok(());
+        }
+        self.open(event.name())?;
+        let history: Vec<f32> = self.history.iter().copied().collect();
+        self.remaining = history.len() as u64 + after;
+        self.write(&history)
+    }
+
+    /// Finalises the file being written.
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.finalize()?;
+        }
+        self.remaining = 0;
+        Ok(())
+    }
+
+    fn samples(&self, ms: u64) -> u64 {
+        ms * self.sample_rate as u64 / 1000
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        for &sample in samples {
+            writer.write_sample(sample)?;
+        }
+        self.remaining -= samples.len() as u64;
+        if self.remaining == 0 {
+            self.finish()?;
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, label: &str) -> Result<()> {
+        self.finish()?;
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        // files opened within the same millisecond are told apart by a counter
+        let mut path = self.config.dir.join(format!("{label}-{millis}.wav"));
+        let mut n = 1;
+        while path.exists() {
+            path = self.config.dir.join(format!("{label}-{millis}-{n}.wav"));
+            n += 1;
+        }
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        self.writer = Some(
+            hound::WavWriter::create(&path, spec)
+                .with_context(|| format!("creating {}", path.display()))?,
+        );
+        self.files.push_back(path);
+        if self.config.max_files > 0 {
+            while self.files.len() > self.config.max_files {
+                let oldest = self.files.pop_front().unwrap();
+                fs::remove_file(&oldest)
+                    .with_context(|| format!("removing {}", oldest.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+enum Command {
+    Push(Vec<f32>),
+    Mark(FroskEvent),
+}
+
+/// Owns a [`Recorder`] running on its own thread, which stops once the
+/// handle is dropped and everything passed to it has been written.
+pub struct RecorderHandle {
+    commands: Option<mpsc::Sender<Command>>,
+    errors: mpsc::Receiver<anyhow::Error>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RecorderHandle {
+    /// Like [`Recorder::push`], without waiting for the chunk to be written.
+    pub fn push(&self, chunk: &[f32]) {
+        self.send(Command::Push(chunk.to_vec()));
+    }
+
+    /// Like [`Recorder::mark`], without waiting for the clip to be written.
+    pub fn mark(&self, event: &FroskEvent) {
+        self.send(Command::Mark(*event));
+    }
+
+    /// The error that stopped the recorder, if it has stopped.
+    pub fn error(&self) -> Option<anyhow::Error> {
+        self.errors.try_recv().ok()
+    }
+
+    /// Waits for everything to be written, returning the error that stopped the recorder if any.
+    pub fn finish(mut self) -> Result<()> {
+        self.join();
+        self.errors.try_recv().map_or(Ok(()), Err)
+    }
+
+    fn send(&self, command: Command) {
+        // the thread only hangs up after an error, which is reported by `error`
+        if let Some(commands) = &self.commands {
+            let _ = commands.send(command);
+        }
+    }
+
+    fn join(&mut self) {
+        self.commands = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for RecorderHandle {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{capture::file::decode_wav, event::Detection};
+    use std::time::Instant;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("frosk-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn read(path: &PathBuf) -> Vec<f32> {
+        decode_wav(File::open(path).unwrap()).unwrap().0
+    }
+
+    #[test]
+    fn test_continuous_recording_rotates_files() {
+        let dir = test_dir("record-continuous");
+        let config = RecorderConfig {
+            dir: dir.clone(),
+            segment_secs: 1,
+            max_files: 2,
+            ..RecorderConfig::default()
+        };
+        let mut recorder = Recorder::new(config, 100).unwrap();
+        let samples: Vec<f32> = (0..250).map(|i| i as f32 / 250.0).collect();
+        for chunk in samples.chunks(30) {
+            recorder.push(chunk).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        // three files of up to 100 samples, of which the first was deleted
+        let files: Vec<PathBuf> = recorder.files().cloned().collect();
+        assert_eq!(files.len(), 2);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        assert_eq!(read(&files[0]), samples[100..200]);
+        assert_eq!(read(&files[1]), samples[200..]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_clips_keep_audio_around_events() {
+        let dir = test_dir("record-clips");
+        let config = RecorderConfig {
+            dir: dir.clone(),
+            mode: RecordMode::Clips,
+            before_ms: 200,
+            after_ms: 100,
+            ..RecorderConfig::default()
+        };
+        let mut recorder = Recorder::new(config, 100).unwrap();
+        let samples: Vec<f32> = (0..100).map(|i| i as f32 / 100.0).collect();
+        let bite = FroskEvent::FishBite(Detection {
+            score: 1.0,
+            sample_offset: 0,
+            sample_index: 0,
+            captured_at: Instant::now(),
+            wall_clock: SystemTime::now(),
+        });
+
+        recorder.push(&samples[..50]).unwrap();
+        recorder.mark(&bite).unwrap();
+        recorder.push(&samples[50..55]).unwrap();
+        // a second event during the clip extends it
+        recorder.mark(&bite).unwrap();
+        recorder.push(&samples[55..]).unwrap();
+        assert_eq!(recorder.files().count(), 1);
+
+        let clip = read(recorder.files().next().unwrap());
+        // 20 samples before the first event, until 10 samples after the second
+        assert_eq!(clip, samples[30..65]);
+        assert!(recorder
+            .files()
+            .next()
+            .unwrap()
+            .to_string_lossy()
+            .contains("FishBite"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_spawned_recorder_writes_in_the_background() {
+        let dir = test_dir("record-spawned");
+        let config = RecorderConfig {
+            dir: dir.clone(),
+            ..RecorderConfig::default()
+        };
+        let recorder = Recorder::new(config, 100).unwrap();
+        let samples: Vec<f32> = (0..250).map(|i| i as f32 / 250.0).collect();
+        let handle = recorder.spawn().unwrap();
+        for chunk in samples.chunks(30) {
+            handle.push(chunk);
+        }
+        handle.finish().unwrap();
+
+        let files: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(read(&files[0]), samples);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}